@@ -0,0 +1,113 @@
+//! Pushes client traffic through the storage actor while gossip ticks run
+//! alongside it, and reports messages handled per second.
+//!
+//! Run with `cargo bench --bench throughput`.
+
+use std::time::{Duration, Instant};
+
+use std::sync::Arc;
+
+use maelstrom_rust_node::{
+    config::Config,
+    dispatch::Dispatcher,
+    rpc::RpcClient,
+    storage::{
+        Storage,
+        actor::{StorageHandle, run_storage},
+    },
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const MESSAGES: u64 = 50_000;
+
+#[tokio::main]
+async fn main() {
+    let (gossip_tx, mut gossip_rx) = mpsc::channel(1024);
+    let (out_tx, mut out_rx) = mpsc::channel(1024);
+    let (handle, rx) = StorageHandle::new(1024);
+    let storage = Storage::new(gossip_tx);
+    let node_id = Arc::clone(&storage.node_id);
+    let hlc = Arc::clone(&storage.hlc);
+    let actor = tokio::spawn(run_storage(storage, rx, out_tx.clone()));
+    let dispatcher = Dispatcher::new(handle.clone(), RpcClient::new(node_id, out_tx.clone(), hlc), out_tx, &Config::default());
+
+    tokio::spawn(async move { while gossip_rx.recv().await.is_some() {} });
+    let counter = tokio::spawn(async move {
+        let mut replies = 0u64;
+        while out_rx.recv().await.is_some() {
+            replies += 1;
+        }
+        replies
+    });
+
+    dispatcher
+        .dispatch(
+            r#"{"src":"c0","dest":"n0","body":{"type":"init","msg_id":0,"node_id":"n0","node_ids":["n0","n1","n2"]}}"#
+                .to_string(),
+        )
+        .await
+        .unwrap();
+    dispatcher
+        .dispatch(
+            r#"{"src":"c0","dest":"n0","body":{"type":"topology","msg_id":0,"topology":{"n0":["n1","n2"]}}}"#
+                .to_string(),
+        )
+        .await
+        .unwrap();
+
+    let stop = CancellationToken::new();
+    let gossip = {
+        let handle = handle.clone();
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            // Same cadence as `spawn_gossip_sender`'s online interval, sped up 10x.
+            let mut interval = tokio::time::interval(Duration::from_millis(100));
+            let mut ticks = 0u64;
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                handle.gossip(true).await.unwrap();
+                ticks += 1;
+            }
+            ticks
+        })
+    };
+
+    let start = Instant::now();
+    for i in 0..MESSAGES {
+        let line = match i % 3 {
+            0 => format!(
+                r#"{{"src":"c1","dest":"n0","body":{{"type":"broadcast","msg_id":{},"message":{}}}}}"#,
+                i, i
+            ),
+            1 => format!(
+                r#"{{"src":"c1","dest":"n0","body":{{"type":"echo","msg_id":{},"echo":"hi"}}}}"#,
+                i
+            ),
+            _ => format!(
+                r#"{{"src":"n1","dest":"n0","body":{{"type":"broadcast_ok","in_reply_to":{}}}}}"#,
+                i
+            ),
+        };
+        dispatcher.dispatch(line).await.unwrap();
+    }
+    drop(dispatcher);
+    drop(handle);
+    stop.cancel();
+    let ticks = gossip.await.unwrap();
+    actor.await.unwrap().unwrap();
+    let elapsed = start.elapsed();
+    let replies = counter.await.unwrap();
+
+    println!(
+        "{} messages ({} replies, {} gossip ticks) in {:?}: {:.0} msgs/sec",
+        MESSAGES,
+        replies,
+        ticks,
+        elapsed,
+        MESSAGES as f64 / elapsed.as_secs_f64()
+    );
+}
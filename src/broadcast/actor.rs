@@ -1,11 +1,11 @@
-use std::time::Duration;
-
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 use crate::{
-    broadcast::{broadcast::send_broadcast, cas::send_cas},
+    broadcast::{broadcast::send_broadcast, cas::send_cas, seq_promise::send_seq_promise},
     message::{BroadcastMessage},
-    storage::NodeId,
+    storage::{NodeId, causal::CausalTag, total_order::Sequenced, wait_for_node_id},
 };
 
 pub enum BroadcastCommand {
@@ -13,6 +13,8 @@ pub enum BroadcastCommand {
         dest: String,
         msg_id: u64,
         message: BroadcastMessage,
+        causal: Option<CausalTag>,
+        seq: Option<Sequenced>,
     },
 
     Cas {
@@ -22,47 +24,119 @@ pub enum BroadcastCommand {
         to: u64,
         create_if_not_exists: bool,
     },
+
+    SeqPromise {
+        dest: String,
+        msg_id: u64,
+        epoch: u64,
+        after: u64,
+    },
 }
+/// Forward commands to stdout until `shutdown` fires, then drain whatever is
+/// already queued so acknowledged work still reaches its peers.
 pub async fn broadcast_message(
-    mut rx: Receiver<BroadcastCommand>,
+    rx: Receiver<BroadcastCommand>,
     tx: Sender<String>,
     id: NodeId,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let Some(id) = wait_for_node_id(&id, &shutdown).await else {
+        return Ok(());
+    };
+    let span = tracing::info_span!("broadcast", node = %id);
+    forward(rx, tx, id, shutdown).instrument(span).await
+}
+
+async fn forward(
+    mut rx: Receiver<BroadcastCommand>,
+    tx: Sender<String>,
+    id: String,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
     loop {
-        let id = {
-            let guard = id.lock().await;
-            if guard.is_none() {
-                drop(guard);
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                continue;
-            }
-            guard.clone().unwrap()
-        };
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(command) => dispatch(&id, command, &tx).await?,
+                None => return Ok(()),
+            },
+            _ = shutdown.cancelled() => break,
+        }
+    }
 
-        // Always handle 'init' globally
-        while let Some(command) = rx.recv().await {
-            match command {
-                BroadcastCommand::Broadcast {
-                    dest,
-                    msg_id,
-                    message,
-                } => send_broadcast(id.clone(), dest, msg_id, message, tx.clone()).await?,
-                BroadcastCommand::Cas {
-                    dest,
-                    msg_id,
-                    from,
-                    to,
-                    create_if_not_exists,
-                } => send_cas(
-                    id.clone(),
-                    dest,
-                    msg_id,
-                    from,
-                    to,
-                    create_if_not_exists,
-                    tx.clone(),
-                ).await?,
-            }
+    tracing::debug!(queued = rx.len(), "draining broadcast queue");
+    while let Ok(command) = rx.try_recv() {
+        dispatch(&id, command, &tx).await?;
+    }
+    Ok(())
+}
+
+async fn dispatch(id: &str, command: BroadcastCommand, tx: &Sender<String>) -> anyhow::Result<()> {
+    match command {
+        BroadcastCommand::Broadcast {
+            dest,
+            msg_id,
+            message,
+            causal,
+            seq,
+        } => send_broadcast(id.to_string(), dest, msg_id, message, causal, seq, tx.clone()).await,
+        BroadcastCommand::Cas {
+            dest,
+            msg_id,
+            from,
+            to,
+            create_if_not_exists,
+        } => {
+            send_cas(
+                id.to_string(),
+                dest,
+                msg_id,
+                from,
+                to,
+                create_if_not_exists,
+                tx.clone(),
+            )
+            .await
         }
+        BroadcastCommand::SeqPromise {
+            dest,
+            msg_id,
+            epoch,
+            after,
+        } => send_seq_promise(id.to_string(), dest, msg_id, epoch, after, tx.clone()).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::{Mutex, mpsc};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn broadcast_message_drains_queue_on_shutdown() {
+        let (cmd_tx, cmd_rx) = mpsc::channel(10);
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        let id = Arc::new(Mutex::new(Some("n1".to_string())));
+        let shutdown = CancellationToken::new();
+
+        cmd_tx
+            .send(BroadcastCommand::Broadcast {
+                dest: "n2".into(),
+                msg_id: 1,
+                message: BroadcastMessage::Single(7),
+                causal: None,
+                seq: None,
+            })
+            .await
+            .unwrap();
+        shutdown.cancel();
+
+        broadcast_message(cmd_rx, out_tx, id, shutdown).await.unwrap();
+
+        let sent: serde_json::Value = serde_json::from_str(&out_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(sent["dest"], "n2");
+        assert!(out_rx.recv().await.is_none());
     }
 }
@@ -1,15 +1,21 @@
 use tokio::sync::mpsc::Sender;
 
-use crate::message::{Body, BroadcastMessage};
+use crate::{
+    message::{Body, BroadcastMessage},
+    storage::{causal::CausalTag, total_order::Sequenced},
+};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn send_broadcast (
     src: String,
     dest: String,
     msg_id: u64,
     message: BroadcastMessage,
+    causal: Option<CausalTag>,
+    seq: Option<Sequenced>,
     tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    let reply = Body::Broadcast { msg_id: msg_id, message: message };
+    let reply = Body::Broadcast { msg_id, message, causal, seq };
     
     let response = serde_json::json!({
         "src": src,
@@ -4,6 +4,7 @@ use crate::{
     message::{Body},
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn send_cas (
     src: String,
     dest: String,
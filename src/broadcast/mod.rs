@@ -1,3 +1,5 @@
 pub mod actor;
+#[allow(clippy::module_inception)]
 pub mod broadcast;
-pub mod cas;
\ No newline at end of file
+pub mod cas;
+pub mod seq_promise;
\ No newline at end of file
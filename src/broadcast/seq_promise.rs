@@ -0,0 +1,23 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::message::Body;
+
+pub async fn send_seq_promise(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    epoch: u64,
+    after: u64,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let request = Body::SeqPromise { msg_id, epoch, after };
+
+    let message = serde_json::json!({
+        "src": src,
+        "dest": dest,
+        "body": request,
+    });
+    let json = serde_json::to_string(&message)?;
+
+    Ok(tx.send(json).await?)
+}
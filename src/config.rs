@@ -0,0 +1,452 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use crate::{counter::CounterMode, ids::IdScheme, snowflake::Layout};
+
+/// Tunables that used to be hard-coded. Later sources override earlier ones:
+/// defaults, then the TOML file named by `--config`/`MAELSTROM_CONFIG`, then
+/// `MAELSTROM_*` environment variables, then command-line flags.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// How often values pending for online peers are retransmitted.
+    pub gossip_interval_ms: u64,
+    /// How often values pending for offline peers are retransmitted.
+    pub offline_gossip_interval_ms: u64,
+    /// How long a peer may go without acking before it is marked offline, used
+    /// until the failure detector has seen enough acks to judge it.
+    pub offline_after_ms: u64,
+    /// Phi above which a peer is suspected and retried at the offline cadence.
+    pub phi_suspect: f64,
+    /// Phi above which a peer is marked offline.
+    pub phi_offline: f64,
+    /// Number of ack intervals the failure detector remembers per peer.
+    pub phi_window: usize,
+    /// Floor on the ack interval standard deviation, so very regular peers
+    /// are not declared dead after a single late ack.
+    pub phi_min_std_dev_ms: f64,
+    /// Capacity of the inbound, outbound and gossip channels.
+    pub channel_capacity: usize,
+    /// Deliver broadcast values in causal order using vector clocks.
+    pub causal_broadcast: bool,
+    /// Deliver broadcast values in one global order chosen by a sequencer.
+    pub total_order_broadcast: bool,
+    /// Longest wait between retransmissions of an unacknowledged value.
+    pub gossip_backoff_max_ms: u64,
+    /// Fraction (0.0-1.0) by which each retransmission delay is randomly shortened.
+    pub gossip_backoff_jitter: f64,
+    /// Upper bound on handlers running concurrently outside the storage actor.
+    pub max_in_flight: usize,
+    /// Remote hybrid clock readings further than this ahead of the local
+    /// wall clock are ignored.
+    pub hlc_max_offset_ms: u64,
+    /// Snowflake epoch, in milliseconds since the Unix epoch.
+    pub snowflake_epoch_ms: u64,
+    /// Bits of each snowflake ID given to the node number.
+    pub snowflake_node_bits: u32,
+    /// Bits of each snowflake ID given to the per-millisecond sequence.
+    pub snowflake_sequence_bits: u32,
+    /// How far ahead of the wall clock generated IDs may run before
+    /// `generate` is refused.
+    pub snowflake_max_borrow_ms: u64,
+    /// Scheme used to answer `generate`: snowflake, uuidv7, ulid or counter.
+    pub id_scheme: IdScheme,
+    /// When non-zero, persist a snowflake high-water mark this far ahead of
+    /// issued IDs so they stay unique across restarts. Needs `data_dir`, and
+    /// may not exceed `snowflake_max_borrow_ms`, or `generate` would be
+    /// refused right after a restart until the clock passed the mark.
+    pub id_reserve_ms: u64,
+    /// How the g-counter workload is served: crdt, seq-kv or per-node-keys.
+    /// Key-less reads only count as counter reads once `init` names the
+    /// g-counter workload.
+    pub counter_mode: CounterMode,
+    /// How long to wait on a Maelstrom KV service before giving up.
+    pub kv_timeout_ms: u64,
+    /// How long replies to `add` and `cas` are kept to answer client
+    /// retries without applying them twice; 0 disables.
+    pub dedupe_window_ms: u64,
+    /// Points each node takes on the consistent-hash ring.
+    pub partition_vnodes: usize,
+    /// Length of the replica list `Storage::replicas_of` returns, owner
+    /// included. The list is all there is: only the owner stores a key and
+    /// nothing is copied to the rest, so `validate` only accepts 1 for now.
+    pub replication_factor: usize,
+    /// Run the SWIM prober. Off by default, since its pings count towards
+    /// Maelstrom's msgs-per-op for workloads that never use membership.
+    pub swim_enabled: bool,
+    /// How often the SWIM prober pings a member.
+    pub swim_probe_interval_ms: u64,
+    /// How long to wait for a direct ping before asking helpers to probe.
+    pub swim_probe_timeout_ms: u64,
+    /// Number of helpers asked to relay an indirect ping.
+    pub swim_indirect_probes: usize,
+    /// How long a member stays suspect before it is confirmed dead.
+    pub swim_suspect_timeout_ms: u64,
+    /// Number of log entries between snapshots for the on-disk backend.
+    pub snapshot_every: usize,
+    /// When set, state is logged under `<data_dir>/<node_id>` and replayed on init.
+    pub data_dir: Option<PathBuf>,
+    /// JSON snapshot to load before processing any input.
+    pub snapshot: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gossip_interval_ms: 1_000,
+            offline_gossip_interval_ms: 3_000,
+            offline_after_ms: 30_000,
+            phi_suspect: 5.0,
+            phi_offline: 8.0,
+            phi_window: 100,
+            phi_min_std_dev_ms: 100.0,
+            channel_capacity: 1024,
+            causal_broadcast: false,
+            total_order_broadcast: false,
+            gossip_backoff_max_ms: 30_000,
+            gossip_backoff_jitter: 0.5,
+            max_in_flight: 256,
+            hlc_max_offset_ms: 500,
+            snowflake_epoch_ms: 1577836800000, // Jan 1 2020 UTC in ms
+            snowflake_node_bits: 10,
+            snowflake_sequence_bits: 12,
+            snowflake_max_borrow_ms: 1_000,
+            id_scheme: IdScheme::default(),
+            id_reserve_ms: 0,
+            counter_mode: CounterMode::default(),
+            kv_timeout_ms: 1_000,
+            dedupe_window_ms: 60_000,
+            partition_vnodes: 64,
+            replication_factor: 1,
+            swim_enabled: false,
+            swim_probe_interval_ms: 1_000,
+            swim_probe_timeout_ms: 300,
+            swim_indirect_probes: 3,
+            swim_suspect_timeout_ms: 5_000,
+            snapshot_every: 1000,
+            data_dir: None,
+            snapshot: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load from the process environment and arguments.
+    pub fn load() -> anyhow::Result<Self> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let env: Vec<(String, String)> = std::env::vars().collect();
+        Self::from_sources(&args, &env)
+    }
+
+    pub fn from_sources(args: &[String], env: &[(String, String)]) -> anyhow::Result<Self> {
+        let flags = parse_flags(args)?;
+        let env_var = |name: &str| {
+            env.iter()
+                .find(|(key, _)| key == &format!("MAELSTROM_{}", name.to_uppercase()))
+                .map(|(_, value)| value.clone())
+        };
+
+        let file = flags
+            .iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| env_var("config"));
+        let mut config = match file {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read config file {}", path))?;
+                toml::from_str(&text).with_context(|| format!("Invalid config file {}", path))?
+            }
+            None => Config::default(),
+        };
+
+        for key in FIELDS {
+            if let Some(value) = env_var(key) {
+                config.set(key, &value)?;
+            }
+        }
+        for (key, value) in &flags {
+            if key != "config" {
+                config.set(key, value)?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reject values the runtime cannot work with: zero intervals and
+    /// capacities panic in tokio, and zero permits stall the dispatcher.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let positive = [
+            ("gossip_interval_ms", self.gossip_interval_ms),
+            ("offline_gossip_interval_ms", self.offline_gossip_interval_ms),
+            ("swim_probe_interval_ms", self.swim_probe_interval_ms),
+            ("channel_capacity", self.channel_capacity as u64),
+            ("max_in_flight", self.max_in_flight as u64),
+            ("phi_window", self.phi_window as u64),
+            ("partition_vnodes", self.partition_vnodes as u64),
+            ("replication_factor", self.replication_factor as u64),
+        ];
+        for (key, value) in positive {
+            if value == 0 {
+                bail!("{} must be at least 1", key);
+            }
+        }
+        if self.phi_suspect >= self.phi_offline {
+            bail!(
+                "phi_suspect ({}) must be below phi_offline ({})",
+                self.phi_suspect,
+                self.phi_offline
+            );
+        }
+        if !(0.0..=1.0).contains(&self.gossip_backoff_jitter) {
+            bail!("gossip_backoff_jitter must be between 0 and 1, got {}", self.gossip_backoff_jitter);
+        }
+        if self.replication_factor > 1 {
+            bail!("replication_factor above 1 is not supported: keys are not copied to replicas");
+        }
+        if self.id_reserve_ms > self.snowflake_max_borrow_ms {
+            bail!(
+                "id_reserve_ms ({}) must not exceed snowflake_max_borrow_ms ({})",
+                self.id_reserve_ms,
+                self.snowflake_max_borrow_ms
+            );
+        }
+        if self.id_reserve_ms > 0 && self.data_dir.is_none() {
+            bail!("id_reserve_ms needs data_dir: reservations are only kept in the log");
+        }
+        self.snowflake_layout()?;
+        Ok(())
+    }
+
+    pub fn gossip_interval(&self) -> Duration {
+        Duration::from_millis(self.gossip_interval_ms)
+    }
+
+    pub fn offline_gossip_interval(&self) -> Duration {
+        Duration::from_millis(self.offline_gossip_interval_ms)
+    }
+
+    pub fn swim_probe_interval(&self) -> Duration {
+        Duration::from_millis(self.swim_probe_interval_ms)
+    }
+
+    pub fn swim_probe_timeout(&self) -> Duration {
+        Duration::from_millis(self.swim_probe_timeout_ms)
+    }
+
+    pub fn kv_timeout(&self) -> Duration {
+        Duration::from_millis(self.kv_timeout_ms)
+    }
+
+    pub fn dedupe_window(&self) -> Duration {
+        Duration::from_millis(self.dedupe_window_ms)
+    }
+
+    /// Snowflake bit layout, or an error if the bit counts are unusable.
+    pub fn snowflake_layout(&self) -> anyhow::Result<Layout> {
+        Layout::new(self.snowflake_node_bits, self.snowflake_sequence_bits)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let parse_err = || format!("Invalid value {:?} for {}", value, key);
+        match key {
+            "gossip_interval_ms" => self.gossip_interval_ms = value.parse().with_context(parse_err)?,
+            "offline_gossip_interval_ms" => {
+                self.offline_gossip_interval_ms = value.parse().with_context(parse_err)?
+            }
+            "offline_after_ms" => self.offline_after_ms = value.parse().with_context(parse_err)?,
+            "phi_suspect" => self.phi_suspect = value.parse().with_context(parse_err)?,
+            "phi_offline" => self.phi_offline = value.parse().with_context(parse_err)?,
+            "phi_window" => self.phi_window = value.parse().with_context(parse_err)?,
+            "phi_min_std_dev_ms" => self.phi_min_std_dev_ms = value.parse().with_context(parse_err)?,
+            "channel_capacity" => self.channel_capacity = value.parse().with_context(parse_err)?,
+            "causal_broadcast" => self.causal_broadcast = value.parse().with_context(parse_err)?,
+            "total_order_broadcast" => self.total_order_broadcast = value.parse().with_context(parse_err)?,
+            "gossip_backoff_max_ms" => self.gossip_backoff_max_ms = value.parse().with_context(parse_err)?,
+            "gossip_backoff_jitter" => self.gossip_backoff_jitter = value.parse().with_context(parse_err)?,
+            "max_in_flight" => self.max_in_flight = value.parse().with_context(parse_err)?,
+            "hlc_max_offset_ms" => self.hlc_max_offset_ms = value.parse().with_context(parse_err)?,
+            "snowflake_epoch_ms" => self.snowflake_epoch_ms = value.parse().with_context(parse_err)?,
+            "snowflake_node_bits" => self.snowflake_node_bits = value.parse().with_context(parse_err)?,
+            "snowflake_sequence_bits" => {
+                self.snowflake_sequence_bits = value.parse().with_context(parse_err)?
+            }
+            "snowflake_max_borrow_ms" => {
+                self.snowflake_max_borrow_ms = value.parse().with_context(parse_err)?
+            }
+            "id_scheme" => self.id_scheme = value.parse()?,
+            "id_reserve_ms" => self.id_reserve_ms = value.parse().with_context(parse_err)?,
+            "counter_mode" => self.counter_mode = value.parse()?,
+            "kv_timeout_ms" => self.kv_timeout_ms = value.parse().with_context(parse_err)?,
+            "dedupe_window_ms" => self.dedupe_window_ms = value.parse().with_context(parse_err)?,
+            "partition_vnodes" => self.partition_vnodes = value.parse().with_context(parse_err)?,
+            "replication_factor" => self.replication_factor = value.parse().with_context(parse_err)?,
+            "swim_enabled" => self.swim_enabled = value.parse().with_context(parse_err)?,
+            "swim_probe_interval_ms" => self.swim_probe_interval_ms = value.parse().with_context(parse_err)?,
+            "swim_probe_timeout_ms" => self.swim_probe_timeout_ms = value.parse().with_context(parse_err)?,
+            "swim_indirect_probes" => self.swim_indirect_probes = value.parse().with_context(parse_err)?,
+            "swim_suspect_timeout_ms" => {
+                self.swim_suspect_timeout_ms = value.parse().with_context(parse_err)?
+            }
+            "snapshot_every" => self.snapshot_every = value.parse().with_context(parse_err)?,
+            "data_dir" => self.data_dir = Some(value.into()),
+            "snapshot" => self.snapshot = Some(value.into()),
+            _ => bail!("Unknown config option {}", key),
+        }
+        Ok(())
+    }
+}
+
+const FIELDS: [&str; 33] = [
+    "gossip_interval_ms",
+    "offline_gossip_interval_ms",
+    "offline_after_ms",
+    "phi_suspect",
+    "phi_offline",
+    "phi_window",
+    "phi_min_std_dev_ms",
+    "channel_capacity",
+    "causal_broadcast",
+    "total_order_broadcast",
+    "gossip_backoff_max_ms",
+    "gossip_backoff_jitter",
+    "max_in_flight",
+    "hlc_max_offset_ms",
+    "snowflake_epoch_ms",
+    "snowflake_node_bits",
+    "snowflake_sequence_bits",
+    "snowflake_max_borrow_ms",
+    "id_scheme",
+    "id_reserve_ms",
+    "counter_mode",
+    "kv_timeout_ms",
+    "dedupe_window_ms",
+    "partition_vnodes",
+    "replication_factor",
+    "swim_enabled",
+    "swim_probe_interval_ms",
+    "swim_probe_timeout_ms",
+    "swim_indirect_probes",
+    "swim_suspect_timeout_ms",
+    "snapshot_every",
+    "data_dir",
+    "snapshot",
+];
+
+/// Accepts `--some-option value` and `--some-option=value`.
+fn parse_flags(args: &[String]) -> anyhow::Result<Vec<(String, String)>> {
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            bail!("Unexpected argument {}", arg);
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .with_context(|| format!("Missing value for --{}", flag))?;
+                (flag.to_string(), value.clone())
+            }
+        };
+        flags.push((key.replace('-', "_"), value));
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_match_previous_constants() {
+        let config = Config::from_sources(&[], &[]).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.gossip_interval(), Duration::from_secs(1));
+        assert_eq!(config.offline_after_ms, 30_000);
+    }
+
+    #[test]
+    fn flags_override_env_which_overrides_file() {
+        let path = std::env::temp_dir().join(format!("maelstrom-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "gossip_interval_ms = 10\noffline_after_ms = 20\nchannel_capacity = 30\n").unwrap();
+        let env = vec![
+            ("MAELSTROM_CONFIG".to_string(), path.display().to_string()),
+            ("MAELSTROM_OFFLINE_AFTER_MS".to_string(), "200".to_string()),
+            ("MAELSTROM_CHANNEL_CAPACITY".to_string(), "300".to_string()),
+        ];
+
+        let config = Config::from_sources(&args(&["--channel-capacity", "3000"]), &env).unwrap();
+
+        assert_eq!(config.gossip_interval_ms, 10);
+        assert_eq!(config.offline_after_ms, 200);
+        assert_eq!(config.channel_capacity, 3000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_flags_and_bad_values() {
+        assert!(Config::from_sources(&args(&["--nope", "1"]), &[]).is_err());
+        assert!(Config::from_sources(&args(&["--gossip-interval-ms=soon"]), &[]).is_err());
+        assert!(Config::from_sources(&args(&["--max-in-flight"]), &[]).is_err());
+        assert!(Config::from_sources(&args(&["--snowflake-node-bits", "20"]), &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_intervals_capacities_and_permits() {
+        for key in [
+            "gossip-interval-ms",
+            "offline-gossip-interval-ms",
+            "swim-probe-interval-ms",
+            "channel-capacity",
+            "max-in-flight",
+            "phi-window",
+            "partition-vnodes",
+            "replication-factor",
+        ] {
+            let flag = format!("--{}=0", key);
+            assert!(Config::from_sources(&args(&[&flag]), &[]).is_err(), "{} accepted 0", key);
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_phi_thresholds_and_out_of_range_jitter() {
+        let bad = [
+            &["--phi-suspect=8", "--phi-offline=8"][..],
+            &["--phi-suspect=9"],
+            &["--gossip-backoff-jitter=1.5"],
+            &["--gossip-backoff-jitter=-0.1"],
+            &["--gossip-backoff-jitter=NaN"],
+        ];
+        for flags in bad {
+            assert!(Config::from_sources(&args(flags), &[]).is_err(), "{:?} accepted", flags);
+        }
+        assert!(Config::from_sources(&args(&["--gossip-backoff-jitter=1"]), &[]).is_ok());
+    }
+
+    #[test]
+    fn rejects_replication_without_replica_sync() {
+        assert!(Config::from_sources(&args(&["--replication-factor=2"]), &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_id_reservations_that_cannot_be_kept() {
+        let with_dir = |flags: &[&str]| {
+            let mut flags = flags.to_vec();
+            flags.push("--data-dir=/tmp/ids");
+            Config::from_sources(&args(&flags), &[])
+        };
+        assert!(with_dir(&["--id-reserve-ms=1000"]).is_ok());
+        assert!(with_dir(&["--id-reserve-ms=1001"]).is_err());
+        assert!(with_dir(&["--id-reserve-ms=50", "--snowflake-max-borrow-ms=10"]).is_err());
+        assert!(Config::from_sources(&args(&["--id-reserve-ms=500"]), &[]).is_err());
+    }
+}
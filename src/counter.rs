@@ -0,0 +1,342 @@
+//! Grow-only counter modes backed by Maelstrom's `seq-kv` service.
+
+use std::{
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{
+    kv::{KvClient, KvClientError},
+    storage::actor::StorageHandle,
+};
+
+/// Key holding the shared total in `seq-kv` mode.
+const COUNTER_KEY: &str = "counter";
+
+/// How many times `add` re-reads and retries after losing a `cas` race.
+const MAX_CAS_ATTEMPTS: usize = 50;
+
+/// How `add` and `read` are served for the g-counter workload, chosen by
+/// `Config::counter_mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CounterMode {
+    /// Per-node CRDT state merged by gossip, answered by the storage actor.
+    #[default]
+    Crdt,
+    /// One shared `seq-kv` key updated with `cas`.
+    SeqKv,
+    /// One `seq-kv` key per node, written only by its owner and summed on
+    /// read, so adds never contend.
+    PerNodeKeys,
+}
+
+impl FromStr for CounterMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "crdt" => Self::Crdt,
+            "seq-kv" => Self::SeqKv,
+            "per-node-keys" => Self::PerNodeKeys,
+            _ => bail!("Unknown counter mode {}; expected crdt, seq-kv or per-node-keys", s),
+        })
+    }
+}
+
+/// A counter served from `seq-kv` instead of the storage actor.
+#[derive(Clone)]
+pub enum KvCounter {
+    SeqKv(SeqKvCounter),
+    PerNodeKeys(PerNodeCounter),
+}
+
+impl KvCounter {
+    /// The counter for `mode`, or `None` when the CRDT path serves it.
+    pub fn for_mode(mode: CounterMode, kv: KvClient, storage: StorageHandle) -> Option<Self> {
+        match mode {
+            CounterMode::Crdt => None,
+            CounterMode::SeqKv => Some(Self::SeqKv(SeqKvCounter::new(kv))),
+            CounterMode::PerNodeKeys => Some(Self::PerNodeKeys(PerNodeCounter::new(kv, storage))),
+        }
+    }
+
+    pub async fn add(&self, delta: u64) -> Result<(), KvClientError> {
+        match self {
+            Self::SeqKv(counter) => counter.add(delta).await,
+            Self::PerNodeKeys(counter) => counter.add(delta).await,
+        }
+    }
+
+    pub async fn read(&self) -> Result<u64, KvClientError> {
+        match self {
+            Self::SeqKv(counter) => counter.read().await,
+            Self::PerNodeKeys(counter) => counter.read().await,
+        }
+    }
+}
+
+/// Counter kept entirely in `seq-kv`. Runs off the storage actor since every
+/// operation waits on the service.
+#[derive(Clone)]
+pub struct SeqKvCounter {
+    kv: KvClient,
+    barrier: Arc<AtomicU64>,
+}
+
+impl SeqKvCounter {
+    pub fn new(kv: KvClient) -> Self {
+        Self {
+            kv,
+            barrier: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Read the total and `cas` it to total + `delta`, re-reading whenever
+    /// another node got there first.
+    pub async fn add(&self, delta: u64) -> Result<(), KvClientError> {
+        let mut attempts = 0;
+        loop {
+            let current = read_or_zero(&self.kv, COUNTER_KEY).await?;
+            match self.kv.cas(COUNTER_KEY, current, current + delta, true).await {
+                Ok(()) => return Ok(()),
+                Err(KvClientError::PreconditionFailed) if attempts + 1 < MAX_CAS_ATTEMPTS => {
+                    attempts += 1;
+                    tracing::debug!(attempts, "counter cas lost a race; retrying");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Current total. `seq-kv` may serve a stale read, so first write a
+    /// fresh value to a key of our own: the read that follows is ordered
+    /// after that write and sees everything before it.
+    pub async fn read(&self) -> Result<u64, KvClientError> {
+        let node = node_id(&self.kv).await?;
+        let stamp = self.barrier.fetch_add(1, Ordering::Relaxed);
+        self.kv.write(&format!("barrier-{}", node), stamp).await?;
+        read_or_zero(&self.kv, COUNTER_KEY).await
+    }
+}
+
+/// Counter split across `counter-<node>` keys in `seq-kv`. Each node only
+/// ever writes its own key, with a blind `write` of its running total.
+#[derive(Clone)]
+pub struct PerNodeCounter {
+    kv: KvClient,
+    storage: StorageHandle,
+    /// This node's contribution, or `None` until it has been loaded from
+    /// `seq-kv`. Held across the write so our own writes never reorder.
+    total: Arc<Mutex<Option<u64>>>,
+}
+
+impl PerNodeCounter {
+    pub fn new(kv: KvClient, storage: StorageHandle) -> Self {
+        Self {
+            kv,
+            storage,
+            total: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Bump this node's total and write it back. A write that definitely
+    /// failed rolls the total back, since the client is told the add did not
+    /// happen; after a timeout it may have landed, so the total is kept.
+    pub async fn add(&self, delta: u64) -> Result<(), KvClientError> {
+        let key = node_key(&node_id(&self.kv).await?);
+        let mut total = self.total.lock().await;
+        let current = match *total {
+            Some(current) => current,
+            // Pick up where a previous run of this node left off.
+            None => read_or_zero(&self.kv, &key).await?,
+        };
+        *total = Some(current + delta);
+        let written = self.kv.write(&key, current + delta).await;
+        if matches!(&written, Err(e) if e.is_definite()) {
+            *total = Some(current);
+        }
+        written
+    }
+
+    /// Sum of every member's key. Our own key is written first, which both
+    /// orders the reads after it and stands in for the `seq-kv` barrier.
+    pub async fn read(&self) -> Result<u64, KvClientError> {
+        let node = node_id(&self.kv).await?;
+        let nodes = self
+            .storage
+            .call(|s| s.node_ids.clone())
+            .await
+            .map_err(|e| KvClientError::Other {
+                code: 11,
+                text: e.to_string(),
+            })?;
+        self.add(0).await?;
+
+        let mut reads = JoinSet::new();
+        for other in nodes.into_iter().filter(|other| other != &node) {
+            let kv = self.kv.clone();
+            reads.spawn(async move { read_or_zero(&kv, &node_key(&other)).await });
+        }
+        let mut sum = self.total.lock().await.unwrap_or_default();
+        while let Some(result) = reads.join_next().await {
+            sum += result.map_err(|e| KvClientError::Other {
+                code: 13,
+                text: e.to_string(),
+            })??;
+        }
+        Ok(sum)
+    }
+}
+
+fn node_key(node: &str) -> String {
+    format!("{}-{}", COUNTER_KEY, node)
+}
+
+async fn node_id(kv: &KvClient) -> Result<String, KvClientError> {
+    kv.rpc().node_id().await.map_err(|e| KvClientError::Other {
+        code: 11,
+        text: e.to_string(),
+    })
+}
+
+async fn read_or_zero(kv: &KvClient, key: &str) -> Result<u64, KvClientError> {
+    match kv.read(key).await {
+        Ok(value) => Ok(value),
+        Err(KvClientError::KeyDoesNotExist) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use serde_json::{Value, json};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        hlc::Hlc,
+        kv::SEQ_KV,
+        rpc::RpcClient,
+        storage::{Storage, actor::run_storage},
+    };
+
+    fn kv_client() -> (KvClient, mpsc::Receiver<String>) {
+        let (tx, rx) = mpsc::channel(10);
+        let node_id = Arc::new(Mutex::new(Some("n1".to_string())));
+        let rpc = RpcClient::new(node_id, tx, Arc::new(Hlc::system()));
+        (KvClient::new(rpc, SEQ_KV, Duration::from_secs(1)), rx)
+    }
+
+    /// Answer `seq-kv` requests from `rx` against `store`, failing the first
+    /// `cas` to simulate a concurrent writer.
+    async fn fake_seq_kv(mut rx: mpsc::Receiver<String>, rpc: RpcClient, mut store: HashMap<String, u64>) {
+        let mut fail_next_cas = true;
+        while let Some(line) = rx.recv().await {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let body = &request["body"];
+            let key = body["key"].as_str().unwrap().to_string();
+            let mut reply = match body["type"].as_str().unwrap() {
+                "read" => match store.get(&key) {
+                    Some(value) => json!({"type": "read_ok", "value": value}),
+                    None => json!({"type": "error", "code": 20, "text": "not found"}),
+                },
+                "write" => {
+                    store.insert(key, body["value"].as_u64().unwrap());
+                    json!({"type": "write_ok"})
+                }
+                "cas" if fail_next_cas => {
+                    fail_next_cas = false;
+                    store.insert(key, 100);
+                    json!({"type": "error", "code": 22, "text": "changed"})
+                }
+                "cas" => {
+                    let current = store.get(&key).copied();
+                    if current.is_none() || current == body["from"].as_u64() {
+                        store.insert(key, body["to"].as_u64().unwrap());
+                        json!({"type": "cas_ok"})
+                    } else {
+                        json!({"type": "error", "code": 22, "text": "changed"})
+                    }
+                }
+                other => panic!("unexpected {}", other),
+            };
+            reply["in_reply_to"] = body["msg_id"].clone();
+            let message = json!({"src": SEQ_KV, "dest": "n1", "body": reply});
+            assert!(rpc.complete(message).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn add_retries_after_precondition_failed_and_read_sees_total() {
+        let (kv, rx) = kv_client();
+        tokio::spawn(fake_seq_kv(rx, kv.rpc().clone(), HashMap::new()));
+        let counter = SeqKvCounter::new(kv);
+
+        counter.add(5).await.unwrap();
+        counter.add(2).await.unwrap();
+
+        assert_eq!(counter.read().await.unwrap(), 107);
+    }
+
+    #[tokio::test]
+    async fn per_node_keys_resume_own_total_and_sum_every_member() {
+        let (kv, rx) = kv_client();
+        let seeded = HashMap::from([("counter-n1".to_string(), 3), ("counter-n2".to_string(), 10)]);
+        tokio::spawn(fake_seq_kv(rx, kv.rpc().clone(), seeded));
+        let (gossip_tx, _gossip_rx) = mpsc::channel(10);
+        let (out_tx, _out_rx) = mpsc::channel(10);
+        let (storage, storage_rx) = StorageHandle::new(10);
+        tokio::spawn(run_storage(Storage::new(gossip_tx), storage_rx, out_tx));
+        storage
+            .call(|s| s.node_ids = vec!["n1".into(), "n2".into(), "n3".into()])
+            .await
+            .unwrap();
+        let counter = PerNodeCounter::new(kv, storage);
+
+        counter.add(4).await.unwrap();
+
+        assert_eq!(counter.read().await.unwrap(), 17);
+    }
+
+    #[tokio::test]
+    async fn per_node_add_rolls_back_a_definitely_failed_write() {
+        let (kv, mut rx) = kv_client();
+        let rpc = kv.rpc().clone();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let seen = written.clone();
+        tokio::spawn(async move {
+            let mut fail_next_write = true;
+            while let Some(line) = rx.recv().await {
+                let body = serde_json::from_str::<Value>(&line).unwrap()["body"].clone();
+                let mut reply = if fail_next_write {
+                    fail_next_write = false;
+                    json!({"type": "error", "code": 11, "text": "unavailable"})
+                } else {
+                    seen.lock().await.push(body["value"].as_u64().unwrap());
+                    json!({"type": "write_ok"})
+                };
+                reply["in_reply_to"] = body["msg_id"].clone();
+                assert!(rpc.complete(json!({"src": SEQ_KV, "dest": "n1", "body": reply})).is_ok());
+            }
+        });
+        let (storage, _storage_rx) = StorageHandle::new(1);
+        let counter = PerNodeCounter::new(kv, storage);
+        *counter.total.lock().await = Some(3);
+
+        assert!(counter.add(4).await.unwrap_err().is_definite());
+        counter.add(1).await.unwrap();
+
+        assert_eq!(*written.lock().await, vec![4]);
+        assert_eq!(*counter.total.lock().await, Some(4));
+    }
+}
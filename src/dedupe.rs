@@ -0,0 +1,215 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::Value;
+use tokio::{
+    sync::mpsc::{self, Sender},
+    time::Instant,
+};
+
+type RequestKey = (String, u64);
+
+/// What to do with an inbound request, by whether its `(src, msg_id)` has
+/// been seen within the window.
+#[derive(Debug)]
+pub enum Seen {
+    /// First sighting: handle it, sending replies through this sender so the
+    /// reply is remembered.
+    New(Sender<String>),
+    /// A retry of a request still being handled; its reply will answer both.
+    InFlight,
+    /// A retry of a finished request: send this reply again.
+    Done(String),
+}
+
+enum State {
+    InFlight,
+    Done(String),
+}
+
+/// A claimed request, stamped with when it was claimed so records left over
+/// from an earlier claim of the same key cannot touch it.
+struct Entry {
+    claimed: Instant,
+    state: State,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<RequestKey, Entry>,
+    /// Keys in insertion order, for expiry. A key claimed again after its
+    /// entry was dropped appears twice; only the record matching the entry's
+    /// `claimed` removes it.
+    order: VecDeque<(Instant, RequestKey)>,
+}
+
+/// Remembers replies to non-idempotent client requests for `window`, so a
+/// client retrying after a timeout gets the original answer instead of
+/// having the request applied twice.
+#[derive(Clone)]
+pub struct DedupeCache {
+    window: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl DedupeCache {
+    /// A zero `window` disables the cache: every request is `New`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            inner: Arc::new(Mutex::new(Inner::default())),
+        }
+    }
+
+    /// Look up `(src, msg_id)`, claiming it if unseen. For a `New` request
+    /// the first line sent through the returned sender that answers it is
+    /// cached; if none is, the claim is dropped so a retry runs again.
+    pub fn begin(&self, src: &str, msg_id: u64, tx: Sender<String>) -> Seen {
+        if self.window.is_zero() {
+            return Seen::New(tx);
+        }
+        let key = (src.to_string(), msg_id);
+        let claimed = Instant::now();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.expire(claimed, self.window);
+            match inner.entries.get(&key).map(|entry| &entry.state) {
+                Some(State::InFlight) => return Seen::InFlight,
+                Some(State::Done(reply)) => return Seen::Done(reply.clone()),
+                None => {}
+            }
+            let entry = Entry {
+                claimed,
+                state: State::InFlight,
+            };
+            inner.entries.insert(key.clone(), entry);
+            inner.order.push_back((claimed, key.clone()));
+        }
+
+        let (reply_tx, mut reply_rx) = mpsc::channel::<String>(4);
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut answered = false;
+            while let Some(line) = reply_rx.recv().await {
+                if !answered && answers(&line, &key) {
+                    answered = true;
+                    cache.finish(&key, claimed, line.clone());
+                }
+                if tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+            if !answered {
+                cache.inner.lock().unwrap().remove(&key, claimed);
+            }
+        });
+        Seen::New(reply_tx)
+    }
+
+    fn finish(&self, key: &RequestKey, claimed: Instant, reply: String) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get_mut(key).filter(|entry| entry.claimed == claimed) {
+            entry.state = State::Done(reply);
+        }
+    }
+}
+
+impl Inner {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some((at, _)) = self.order.front() {
+            if now.duration_since(*at) < window {
+                break;
+            }
+            let (at, key) = self.order.pop_front().unwrap();
+            self.remove(&key, at);
+        }
+    }
+
+    /// Drop `key`'s entry if it is still the claim made at `claimed`.
+    fn remove(&mut self, key: &RequestKey, claimed: Instant) {
+        if self.entries.get(key).is_some_and(|entry| entry.claimed == claimed) {
+            self.entries.remove(key);
+        }
+    }
+}
+
+/// Whether `line` is the reply to the request `key` identifies.
+fn answers(line: &str, (src, msg_id): &RequestKey) -> bool {
+    let Ok(message) = serde_json::from_str::<Value>(line) else {
+        return false;
+    };
+    message["dest"] == src.as_str() && message["body"]["in_reply_to"].as_u64() == Some(*msg_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn reply(dest: &str, in_reply_to: u64) -> String {
+        json!({"src": "n1", "dest": dest, "body": {"type": "add_ok", "in_reply_to": in_reply_to}}).to_string()
+    }
+
+    #[tokio::test]
+    async fn retried_request_gets_cached_reply_until_it_expires() {
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        let cache = DedupeCache::new(Duration::from_millis(200));
+
+        let Seen::New(tx) = cache.begin("c1", 1, out_tx.clone()) else {
+            panic!("first sighting should be new");
+        };
+        assert!(matches!(cache.begin("c1", 1, out_tx.clone()), Seen::InFlight));
+        assert!(matches!(cache.begin("c2", 1, out_tx.clone()), Seen::New(_)));
+
+        tx.send(reply("c1", 1)).await.unwrap();
+        drop(tx);
+        assert_eq!(out_rx.recv().await.unwrap(), reply("c1", 1));
+        tokio::task::yield_now().await;
+        assert!(matches!(cache.begin("c1", 1, out_tx.clone()), Seen::Done(line) if line == reply("c1", 1)));
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(matches!(cache.begin("c1", 1, out_tx), Seen::New(_)));
+    }
+
+    #[tokio::test]
+    async fn unanswered_request_can_be_retried() {
+        let (out_tx, _out_rx) = mpsc::channel(10);
+        let cache = DedupeCache::new(Duration::from_secs(10));
+
+        let Seen::New(tx) = cache.begin("c1", 1, out_tx.clone()) else {
+            panic!("first sighting should be new");
+        };
+        drop(tx);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(matches!(cache.begin("c1", 1, out_tx), Seen::New(_)));
+    }
+
+    #[tokio::test]
+    async fn stale_expiry_record_does_not_drop_a_later_claim() {
+        let (out_tx, _out_rx) = mpsc::channel(10);
+        let cache = DedupeCache::new(Duration::from_millis(200));
+
+        let Seen::New(tx) = cache.begin("c1", 1, out_tx.clone()) else {
+            panic!("first sighting should be new");
+        };
+        drop(tx);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let Seen::New(_tx) = cache.begin("c1", 1, out_tx.clone()) else {
+            panic!("an unanswered request should be claimable again");
+        };
+
+        // The first claim's record expires here; the second claim must survive it.
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(matches!(cache.begin("c1", 1, out_tx), Seen::InFlight));
+    }
+}
@@ -0,0 +1,409 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use serde_json::Value;
+use tokio::sync::{Semaphore, mpsc::Sender};
+use tracing::{Instrument, Span};
+
+use crate::{
+    config::Config,
+    counter::KvCounter,
+    dedupe::{DedupeCache, Seen},
+    forward::forward,
+    handlers::{
+        counter::{handle_kv_counter_add, handle_kv_counter_read},
+        echo::handle_echo,
+        ping::handle_ping_req,
+    },
+    kv::{KvClient, SEQ_KV},
+    message::{Body, Message},
+    rpc::RpcClient,
+    storage::actor::StorageHandle,
+};
+
+/// Routes each inbound line to where it can run without holding up the rest:
+/// RPC replies go straight to their waiting caller, handlers that never touch
+/// `Storage` run on their own task, and everything else is queued on the
+/// storage actor in arrival order, which keeps per-key ordering intact.
+pub struct Dispatcher {
+    storage: StorageHandle,
+    rpc: RpcClient,
+    tx: Sender<String>,
+    permits: Arc<Semaphore>,
+    probe_timeout: Duration,
+    /// Set when `add` and counter reads are served from `seq-kv` rather
+    /// than the storage actor.
+    counter: Option<KvCounter>,
+    dedupe: DedupeCache,
+    /// How long a request forwarded to its key's owner waits for a reply.
+    forward_timeout: Duration,
+}
+
+impl Dispatcher {
+    pub fn new(storage: StorageHandle, rpc: RpcClient, tx: Sender<String>, config: &Config) -> Self {
+        let kv = KvClient::new(rpc.clone(), SEQ_KV, config.kv_timeout());
+        let counter = KvCounter::for_mode(config.counter_mode, kv, storage.clone());
+        Self {
+            storage,
+            rpc,
+            tx,
+            permits: Arc::new(Semaphore::new(config.max_in_flight)),
+            probe_timeout: config.swim_probe_timeout(),
+            counter,
+            dedupe: DedupeCache::new(config.dedupe_window()),
+            forward_timeout: config.kv_timeout(),
+        }
+    }
+
+    pub async fn dispatch(&self, line: String) -> anyhow::Result<()> {
+        let value: Value =
+            serde_json::from_str(&line).with_context(|| format!("Invalid JSON: {}", line))?;
+        let span = message_span(&value);
+        span.in_scope(|| tracing::trace!(%line, "received"));
+        self.rpc.hlc().observe(&value);
+        let Err(value) = span.in_scope(|| self.rpc.complete(value)) else {
+            return Ok(());
+        };
+        let msg: Message = serde_json::from_value(value).context("Unknown message")?;
+
+        // A client's keyed request is served by the key's owner alone, so
+        // there is one copy of each register. Requests from other nodes are
+        // served here, so nodes that briefly disagree about ownership cannot
+        // bounce them around.
+        let (from_member, owner) = match keyed(&msg.body) {
+            Some(key) => {
+                let (src, key) = (msg.src.clone(), key.to_string());
+                self.storage
+                    .call(move |s| {
+                        let from_member = s.node_ids.contains(&src);
+                        let owner = match from_member {
+                            true => None,
+                            false => s.remote_owner_of(&key).map(str::to_string),
+                        };
+                        (from_member, owner)
+                    })
+                    .await?
+            }
+            None => (false, None),
+        };
+
+        // Keyed requests from other nodes are forwarded ones, which are never
+        // retried, and the sender's msg_ids restart with it, so they skip the
+        // dedupe cache lest a cached reply answer an unrelated request.
+        let reply_tx = match non_idempotent(&msg.body).filter(|_| !from_member) {
+            Some(msg_id) => match self.dedupe.begin(&msg.src, msg_id, self.tx.clone()) {
+                Seen::New(reply_tx) => Some(reply_tx),
+                // Not answered here: the original's reply carries the same
+                // `in_reply_to` and dest, so it answers the retry as well.
+                Seen::InFlight => {
+                    span.in_scope(|| tracing::info!("retry of a request in flight; the original reply will answer it"));
+                    return Ok(());
+                }
+                Seen::Done(reply) => {
+                    span.in_scope(|| tracing::debug!("retry of a finished request; resending reply"));
+                    return Ok(self.tx.send(reply).await?);
+                }
+            },
+            None => None,
+        };
+
+        if let Some(owner) = owner {
+            let tx = reply_tx.unwrap_or_else(|| self.tx.clone());
+            let (rpc, timeout) = (self.rpc.clone(), self.forward_timeout);
+            let handler = async move { forward(&rpc, &tx, &owner, msg, timeout).await };
+            return self.spawn(handler.instrument(span)).await;
+        }
+
+        // Key-less reads are counter reads only under g-counter, as in
+        // `process_message`; a broadcast read still belongs to the storage actor.
+        let counter_read = self.counter.is_some()
+            && matches!(msg.body, Body::Read { key: None, .. })
+            && self
+                .storage
+                .call(|s| s.workload.as_deref() == Some("g-counter"))
+                .await?;
+
+        match (&self.counter, msg.body) {
+            (_, Body::Echo { msg_id, echo }) => {
+                let tx = self.tx.clone();
+                self.spawn(handle_echo(msg.src, msg.dest, msg_id, echo, tx).instrument(span))
+                    .await
+            }
+            (
+                _,
+                Body::PingReq {
+                    msg_id,
+                    target,
+                    updates,
+                },
+            ) => {
+                let handler = handle_ping_req(
+                    msg.src,
+                    msg.dest,
+                    msg_id,
+                    target,
+                    updates,
+                    self.storage.clone(),
+                    self.rpc.clone(),
+                    self.tx.clone(),
+                    self.probe_timeout,
+                );
+                self.spawn(handler.instrument(span)).await
+            }
+            (Some(counter), Body::Add { msg_id, delta }) => {
+                let tx = reply_tx.unwrap_or_else(|| self.tx.clone());
+                let handler = handle_kv_counter_add(msg.src, msg.dest, msg_id, delta, counter.clone(), tx);
+                self.spawn(handler.instrument(span)).await
+            }
+            (Some(counter), Body::Read { msg_id, key: None }) if counter_read => {
+                let handler =
+                    handle_kv_counter_read(msg.src, msg.dest, msg_id, counter.clone(), self.tx.clone());
+                self.spawn(handler.instrument(span)).await
+            }
+            (_, body) => {
+                let msg = Message {
+                    src: msg.src,
+                    dest: msg.dest,
+                    body,
+                };
+                match reply_tx {
+                    Some(reply_tx) => self.storage.process_replying(msg, span, reply_tx).await,
+                    None => self.storage.process(msg, span).await,
+                }
+            }
+        }
+    }
+
+    /// Run `handler` on its own task once a permit is free. Waiting for the
+    /// permit here is what bounds the number of handlers in flight.
+    async fn spawn<F>(&self, handler: F) -> anyhow::Result<()>
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let permit = Arc::clone(&self.permits).acquire_owned().await?;
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = handler.await {
+                tracing::warn!(error = %e, "handler failed");
+            }
+        });
+        Ok(())
+    }
+}
+
+/// The `msg_id` of requests that must not be applied twice when a client
+/// retries them; their replies go through the dedupe cache.
+fn non_idempotent(body: &Body) -> Option<u64> {
+    match body {
+        Body::Add { msg_id, .. } | Body::Cas { msg_id, .. } => Some(*msg_id),
+        _ => None,
+    }
+}
+
+/// The register key a lin-kv request reads or writes.
+fn keyed(body: &Body) -> Option<&str> {
+    match body {
+        Body::Read { key: Some(key), .. } | Body::Write { key, .. } | Body::Cas { key, .. } => Some(key),
+        _ => None,
+    }
+}
+
+/// Span carried by everything done on behalf of one inbound message. Inbound
+/// `dest` is always this node, so it doubles as the node id field. It is an
+/// info span so warnings at the default log level still name the message.
+fn message_span(value: &Value) -> Span {
+    let body = &value["body"];
+    tracing::info_span!(
+        "msg",
+        node = value["dest"].as_str().unwrap_or_default(),
+        src = value["src"].as_str().unwrap_or_default(),
+        r#type = body["type"].as_str().unwrap_or_default(),
+        msg_id = body["msg_id"].as_u64(),
+        in_reply_to = body["in_reply_to"].as_u64(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::{Mutex, mpsc};
+
+    use crate::hlc::Hlc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn echo_is_answered_while_storage_is_blocked() {
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        // Nobody drains the storage queue, so anything routed there would hang.
+        let (storage, _storage_rx) = StorageHandle::new(1);
+        let rpc = RpcClient::new(Arc::new(Mutex::new(Some("n1".into()))), out_tx.clone(), Arc::new(Hlc::system()));
+        let dispatcher = Dispatcher::new(storage, rpc, out_tx, &Config::default());
+
+        dispatcher
+            .dispatch(r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":1}}"#.into())
+            .await
+            .unwrap();
+        dispatcher
+            .dispatch(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2,"echo":"hi"}}"#.into())
+            .await
+            .unwrap();
+
+        let reply = tokio::time::timeout(Duration::from_secs(1), out_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let reply: Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["body"]["type"], "echo_ok");
+    }
+
+    #[tokio::test]
+    async fn retried_add_is_applied_once_and_answered_twice() {
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        let (gossip_tx, _gossip_rx) = mpsc::channel(10);
+        let (storage, storage_rx) = StorageHandle::new(10);
+        tokio::spawn(crate::storage::actor::run_storage(
+            crate::storage::Storage::new(gossip_tx),
+            storage_rx,
+            out_tx.clone(),
+        ));
+        let rpc = RpcClient::new(Arc::new(Mutex::new(Some("n1".into()))), out_tx.clone(), Arc::new(Hlc::system()));
+        let dispatcher = Dispatcher::new(storage.clone(), rpc, out_tx, &Config::default());
+
+        let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+        dispatcher.dispatch(init.into()).await.unwrap();
+        out_rx.recv().await.unwrap();
+        let add = r#"{"src":"c1","dest":"n1","body":{"type":"add","msg_id":2,"delta":5}}"#;
+        for _ in 0..2 {
+            dispatcher.dispatch(add.into()).await.unwrap();
+            let reply: Value = serde_json::from_str(&out_rx.recv().await.unwrap()).unwrap();
+            assert_eq!(reply["body"]["type"], "add_ok");
+        }
+
+        assert_eq!(storage.call(|s| s.g_counter_value()).await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn broadcast_read_is_not_taken_for_a_counter_read() {
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        let (gossip_tx, _gossip_rx) = mpsc::channel(10);
+        let (storage, storage_rx) = StorageHandle::new(10);
+        tokio::spawn(crate::storage::actor::run_storage(
+            crate::storage::Storage::new(gossip_tx),
+            storage_rx,
+            out_tx.clone(),
+        ));
+        storage.call(|s| s.workload = Some("broadcast".into())).await.unwrap();
+        let rpc = RpcClient::new(Arc::new(Mutex::new(Some("n1".into()))), out_tx.clone(), Arc::new(Hlc::system()));
+        let config = Config {
+            counter_mode: crate::counter::CounterMode::SeqKv,
+            ..Config::default()
+        };
+        let dispatcher = Dispatcher::new(storage, rpc, out_tx, &config);
+
+        dispatcher
+            .dispatch(r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":1}}"#.into())
+            .await
+            .unwrap();
+
+        let reply: Value = serde_json::from_str(&out_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["dest"], "c1");
+        assert_eq!(reply["body"]["type"], "read_ok");
+        assert!(reply["body"]["messages"].is_array());
+    }
+
+    async fn initialised_node(
+        id: &str,
+        node_ids: &str,
+        replication_factor: usize,
+    ) -> (Dispatcher, StorageHandle, mpsc::Receiver<String>) {
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        let (gossip_tx, mut gossip_rx) = mpsc::channel(10);
+        tokio::spawn(async move { while gossip_rx.recv().await.is_some() {} });
+        let (storage, storage_rx) = StorageHandle::new(10);
+        tokio::spawn(crate::storage::actor::run_storage(
+            crate::storage::Storage::new(gossip_tx),
+            storage_rx,
+            out_tx.clone(),
+        ));
+        storage
+            .call(move |s| s.config.replication_factor = replication_factor)
+            .await
+            .unwrap();
+        let rpc = RpcClient::new(Arc::new(Mutex::new(Some(id.into()))), out_tx.clone(), Arc::new(Hlc::system()));
+        let dispatcher = Dispatcher::new(storage.clone(), rpc, out_tx, &Config::default());
+        let init = format!(
+            r#"{{"src":"c0","dest":"{id}","body":{{"type":"init","msg_id":1,"node_id":"{id}","node_ids":{node_ids}}}}}"#
+        );
+        dispatcher.dispatch(init).await.unwrap();
+        out_rx.recv().await.unwrap();
+        (dispatcher, storage, out_rx)
+    }
+
+    /// Write a key owned by n2 through n1 and check only n2 applied it.
+    async fn write_through_n1_reaches_the_owner(replication_factor: usize) {
+        let members = vec!["n1".to_string(), "n2".to_string()];
+        let ring = crate::storage::partition::HashRing::new(&members, Config::default().partition_vnodes);
+        let key: String = (0..)
+            .map(|i| format!("k{}", i))
+            .find(|key| ring.owner(key) == Some("n2"))
+            .unwrap();
+        let (n1, n1_storage, mut n1_out) = initialised_node("n1", r#"["n1","n2"]"#, replication_factor).await;
+        let (n2, n2_storage, mut n2_out) = initialised_node("n2", r#"["n1","n2"]"#, replication_factor).await;
+
+        let write = json!({"src": "c1", "dest": "n1", "body": {"type": "write", "msg_id": 5, "key": key, "value": 9}});
+        n1.dispatch(write.to_string()).await.unwrap();
+        let forwarded = n1_out.recv().await.unwrap();
+        let request: Value = serde_json::from_str(&forwarded).unwrap();
+        assert_eq!((request["src"].as_str(), request["dest"].as_str()), (Some("n1"), Some("n2")));
+        n2.dispatch(forwarded).await.unwrap();
+        n1.dispatch(n2_out.recv().await.unwrap()).await.unwrap();
+
+        let reply: Value = serde_json::from_str(&n1_out.recv().await.unwrap()).unwrap();
+        assert_eq!(reply, json!({"src": "n1", "dest": "c1", "body": {"type": "write_ok", "in_reply_to": 5}}));
+        let owned = key.clone();
+        assert_eq!(n2_storage.call(move |s| s.kv_read(&owned).ok()).await.unwrap(), Some(9));
+        assert_eq!(n1_storage.call(move |s| s.kv_read(&key).ok()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn write_to_a_non_owner_is_served_by_the_owner() {
+        write_through_n1_reaches_the_owner(1).await;
+    }
+
+    #[tokio::test]
+    async fn write_to_a_replica_is_still_served_by_the_owner() {
+        // With two nodes and two replicas n1 holds every key, but only the
+        // owner may accept writes or the copies would diverge.
+        write_through_n1_reaches_the_owner(2).await;
+    }
+
+    #[tokio::test]
+    async fn requests_from_members_are_not_deduplicated() {
+        let (n1, storage, mut out) = initialised_node("n1", r#"["n1","n2"]"#, 1).await;
+
+        for (from, to) in [(0, 1), (1, 2)] {
+            let cas = json!({"src": "n2", "dest": "n1", "body": {
+                "type": "cas", "msg_id": 1, "key": "k", "from": from, "to": to, "create_if_not_exists": true,
+            }});
+            n1.dispatch(cas.to_string()).await.unwrap();
+            let reply: Value = serde_json::from_str(&out.recv().await.unwrap()).unwrap();
+            assert_eq!(reply["body"]["type"], "cas_ok");
+        }
+
+        assert_eq!(storage.call(|s| s.kv_read("k").ok()).await.unwrap(), Some(2));
+    }
+
+    #[test]
+    fn message_span_is_recorded_at_the_default_log_level() {
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new("info"))
+            .with_writer(std::io::sink)
+            .finish();
+        let line = serde_json::json!({"src": "c1", "dest": "n1", "body": {"type": "add", "msg_id": 3}});
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!message_span(&line).is_disabled());
+        });
+    }
+}
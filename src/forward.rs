@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::mpsc::Sender;
+
+use crate::{message::Message, rpc::RpcClient};
+
+/// Hand a client `request` to `owner` and relay the owner's reply back to
+/// the client as if this node had answered it.
+///
+/// The owner sees a request from this node under a fresh `msg_id`; its reply
+/// comes back through the RPC layer and is rewritten to come from
+/// `request.dest`, go to `request.src` and answer the client's own `msg_id`.
+/// If the owner does not answer within `timeout` the client gets an
+/// indefinite error, since the owner may still have applied the request.
+///
+/// Owners should serve requests from other nodes locally rather than
+/// forwarding them again, so nodes that briefly disagree about ownership
+/// cannot pass a request back and forth.
+pub async fn forward(
+    rpc: &RpcClient,
+    tx: &Sender<String>,
+    owner: &str,
+    request: Message,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut body = serde_json::to_value(&request.body)?;
+    let client_msg_id = body["msg_id"]
+        .take()
+        .as_u64()
+        .context("Only requests with a msg_id can be forwarded")?;
+
+    let mut reply = match rpc.call(owner, body, timeout).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            tracing::warn!(owner, error = %e, "forwarded request went unanswered");
+            serde_json::json!({
+                "type": "error",
+                "code": 0,
+                "text": format!("no reply from owner {}", owner),
+            })
+        }
+    };
+    if let Some(fields) = reply.as_object_mut() {
+        fields.remove("msg_id");
+    }
+    reply["in_reply_to"] = client_msg_id.into();
+
+    let response = serde_json::json!({
+        "src": request.dest,
+        "dest": request.src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::{Value, json};
+    use tokio::sync::{Mutex, mpsc};
+
+    use super::*;
+    use crate::hlc::Hlc;
+
+    fn client_add() -> Message {
+        serde_json::from_value(json!({
+            "src": "c1",
+            "dest": "n1",
+            "body": {"type": "add", "msg_id": 7, "delta": 3},
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn owner_reply_is_relayed_to_the_client() {
+        let (node_tx, mut node_rx) = mpsc::channel(10);
+        let (client_tx, mut client_rx) = mpsc::channel(10);
+        let rpc = RpcClient::new(Arc::new(Mutex::new(Some("n1".into()))), node_tx, Arc::new(Hlc::system()));
+
+        let forwarding = {
+            let rpc = rpc.clone();
+            tokio::spawn(async move { forward(&rpc, &client_tx, "n2", client_add(), Duration::from_secs(1)).await })
+        };
+
+        let request: Value = serde_json::from_str(&node_rx.recv().await.unwrap()).unwrap();
+        assert_eq!((request["src"].as_str(), request["dest"].as_str()), (Some("n1"), Some("n2")));
+        assert_eq!(request["body"]["type"], "add");
+        assert_eq!(request["body"]["delta"], 3);
+        let owner_msg_id = request["body"]["msg_id"].clone();
+        let owner_reply = json!({
+            "src": "n2",
+            "dest": "n1",
+            "body": {"type": "add_ok", "msg_id": 40, "in_reply_to": owner_msg_id},
+        });
+        assert!(rpc.complete(owner_reply).is_ok());
+        forwarding.await.unwrap().unwrap();
+
+        let reply: Value = serde_json::from_str(&client_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            reply,
+            json!({"src": "n1", "dest": "c1", "body": {"type": "add_ok", "in_reply_to": 7}})
+        );
+    }
+
+    #[tokio::test]
+    async fn silent_owner_yields_an_indefinite_error() {
+        let (node_tx, _node_rx) = mpsc::channel(10);
+        let (client_tx, mut client_rx) = mpsc::channel(10);
+        let rpc = RpcClient::new(Arc::new(Mutex::new(Some("n1".into()))), node_tx, Arc::new(Hlc::system()));
+
+        forward(&rpc, &client_tx, "n2", client_add(), Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        let reply: Value = serde_json::from_str(&client_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["dest"], "c1");
+        assert_eq!(reply["body"]["type"], "error");
+        assert_eq!(reply["body"]["code"], 0);
+        assert_eq!(reply["body"]["in_reply_to"], 7);
+    }
+}
@@ -1,6 +1,9 @@
 use tokio::sync::mpsc::Sender;
 
-use crate::{message::ReplyBody, storage::Storage};
+use crate::{
+    message::ReplyBody,
+    storage::{Storage, backend::NOT_LOGGED},
+};
 
 pub async fn handle_add(
     src: String,
@@ -10,9 +13,18 @@ pub async fn handle_add(
     delta: u64,
     tx: Sender<String>
 ) -> anyhow::Result<()> {
-    storage.process_add(delta, src.clone(), msg_id).await;
-    let reply = ReplyBody::AddOk {
-        in_reply_to: msg_id,
+    let reply = match storage.process_add(delta, src.clone(), msg_id).await {
+        Ok(()) => ReplyBody::AddOk {
+            in_reply_to: msg_id,
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "add was not logged");
+            ReplyBody::Error {
+                in_reply_to: msg_id,
+                code: NOT_LOGGED,
+                text: e.to_string(),
+            }
+        }
     };
 
     let response = serde_json::json!({
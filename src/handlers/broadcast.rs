@@ -2,26 +2,39 @@ use tokio::sync::mpsc::Sender;
 
 use crate::{
     message::{BroadcastMessage, ReplyBody},
-    storage::Storage,
+    storage::{Storage, backend::NOT_LOGGED, causal::CausalTag, total_order::Sequenced},
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_broadcast(
     src: String,
     dest: String,
     msg_id: u64,
     storage: &mut Storage,
     message: BroadcastMessage,
+    causal: Option<CausalTag>,
+    seq: Option<Sequenced>,
     tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    match message {
-        BroadcastMessage::Single(value) => {storage.update_values(src.clone(), value)},
-        BroadcastMessage::Hashmap(values ) => storage.update_counter(values),
-        _ => {}
-
-    }
-    let reply = ReplyBody::BroadcastOk {
-        in_reply_to: msg_id,
+    let applied = match message {
+        BroadcastMessage::Single(value) if storage.config.total_order_broadcast => {
+            storage.broadcast_total(src.clone(), msg_id, value, seq)
+        }
+        BroadcastMessage::Single(value) if storage.config.causal_broadcast => {
+            storage.broadcast_causal(src.clone(), value, causal)
+        }
+        BroadcastMessage::Single(value) => storage.update_values(src.clone(), value),
+        // An empty numbered broadcast is a no-op closing a gap in the order.
+        BroadcastMessage::Multiple(values) if values.is_empty() && storage.config.total_order_broadcast => {
+            if let Some(seq) = seq {
+                storage.learn_noop(src.clone(), seq);
+            }
+            Ok(())
+        }
+        BroadcastMessage::Hashmap(values) => storage.update_counter(values, Some(&src)).await,
+        _ => Ok(()),
     };
+    let reply = broadcast_reply(msg_id, applied);
     let response = serde_json::json!({
         "src": dest,
         "dest": src,
@@ -40,13 +53,11 @@ pub async fn handle_broadcast_g_counter(
     message: BroadcastMessage,
     tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    match message {
-        BroadcastMessage::Hashmap(value) => storage.update_counter(value),
-        _ => {}
-    }
-    let reply = ReplyBody::BroadcastOk {
-        in_reply_to: msg_id,
+    let applied = match message {
+        BroadcastMessage::Hashmap(value) => storage.update_counter(value, Some(&src)).await,
+        _ => Ok(()),
     };
+    let reply = broadcast_reply(msg_id, applied);
     let response = serde_json::json!({
         "src": dest,
         "dest": src,
@@ -56,3 +67,21 @@ pub async fn handle_broadcast_g_counter(
 
     Ok(tx.send(json).await?)
 }
+
+/// Acknowledge a broadcast only once it is logged; otherwise the sender
+/// gets an error and, being a peer, keeps retransmitting it.
+fn broadcast_reply(msg_id: u64, applied: anyhow::Result<()>) -> ReplyBody {
+    match applied {
+        Ok(()) => ReplyBody::BroadcastOk {
+            in_reply_to: msg_id,
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "broadcast was not logged");
+            ReplyBody::Error {
+                in_reply_to: msg_id,
+                code: NOT_LOGGED,
+                text: e.to_string(),
+            }
+        }
+    }
+}
@@ -9,6 +9,7 @@ pub async fn handle_broadcast_ok (
     in_reply_to: u64,
     storage: &mut Storage,
 ) -> anyhow::Result<()> {
+    storage.counter_acked(&src, in_reply_to);
     storage.remove_from_peer_pending(src, in_reply_to);
     Ok(())
 }
@@ -0,0 +1,35 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::{message::ReplyBody, storage::Storage};
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_cas(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    key: String,
+    from: u64,
+    to: u64,
+    create_if_not_exists: bool,
+    storage: &mut Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let reply = match storage.kv_cas(key, from, to, create_if_not_exists) {
+        Ok(()) => ReplyBody::CasOk {
+            in_reply_to: msg_id,
+        },
+        Err(e) => ReplyBody::Error {
+            in_reply_to: msg_id,
+            code: e.code(),
+            text: e.text(),
+        },
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
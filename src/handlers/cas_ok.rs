@@ -8,6 +8,5 @@ pub async fn handle_cas_ok(
     storage: &mut Storage,
     _tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    storage.remove_request_from_pending_cas(in_reply_to);
-    Ok(())
+    storage.remove_request_from_pending_cas(in_reply_to).await
 }
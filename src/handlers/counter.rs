@@ -0,0 +1,61 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    counter::KvCounter,
+    kv::KvClientError,
+    message::{ReadMessage, ReplyBody},
+};
+
+pub async fn handle_kv_counter_add(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    delta: u64,
+    counter: KvCounter,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let reply = match counter.add(delta).await {
+        Ok(()) => ReplyBody::AddOk { in_reply_to: msg_id },
+        Err(e) => error_reply(msg_id, e),
+    };
+    send(src, dest, reply, tx).await
+}
+
+pub async fn handle_kv_counter_read(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    counter: KvCounter,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let reply = match counter.read().await {
+        Ok(value) => ReplyBody::ReadOk {
+            in_reply_to: msg_id,
+            messages: ReadMessage::Single(value),
+        },
+        Err(e) => error_reply(msg_id, e),
+    };
+    send(src, dest, reply, tx).await
+}
+
+/// A definite failure is reported as temporarily unavailable so the client
+/// knows the add did not happen; anything else may have applied.
+fn error_reply(msg_id: u64, error: KvClientError) -> ReplyBody {
+    tracing::warn!(error = %error, "kv counter request failed");
+    ReplyBody::Error {
+        in_reply_to: msg_id,
+        code: if error.is_definite() { 11 } else { 0 },
+        text: error.to_string(),
+    }
+}
+
+async fn send(src: String, dest: String, reply: ReplyBody, tx: Sender<String>) -> anyhow::Result<()> {
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
@@ -0,0 +1,24 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::{message::ReplyBody, storage::Storage};
+
+pub async fn handle_dump(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    storage: &Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let reply = ReplyBody::DumpOk {
+        in_reply_to: msg_id,
+        snapshot: Box::new(storage.export_snapshot()),
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
@@ -13,7 +13,7 @@ pub async fn handle_echo(
 ) -> anyhow::Result<()> {
     let reply = ReplyBody::EchoOk {
         in_reply_to: msg_id,
-        echo: echo
+        echo,
     };
 
     let response = serde_json::json!({
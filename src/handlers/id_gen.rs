@@ -11,9 +11,19 @@ pub async fn handle_id_gen (
     storage: &mut Storage,
     tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    let reply = ReplyBody::GenerateOk  {
-        in_reply_to: msg_id,
-        id: storage.next_id().to_string()
+    let reply = match storage.generate_id() {
+        Ok(id) => ReplyBody::GenerateOk {
+            in_reply_to: msg_id,
+            id,
+        },
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot generate id");
+            ReplyBody::Error {
+                in_reply_to: msg_id,
+                code: 11,
+                text: e.to_string(),
+            }
+        }
     };
 
     let response = serde_json::json!({
@@ -2,9 +2,15 @@ pub mod init;
 pub mod add;
 pub mod broadcast;
 pub mod broadcast_ok;
+pub mod cas;
 pub mod cas_ok;
+pub mod counter;
+pub mod dump;
 pub mod echo;
 pub mod error;
 pub mod id_gen;
+pub mod ping;
 pub mod read;
-pub mod topology;
\ No newline at end of file
+pub mod seq_promise;
+pub mod topology;
+pub mod write;
\ No newline at end of file
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    message::ReplyBody,
+    rpc::RpcClient,
+    storage::{Storage, actor::StorageHandle, membership::MemberUpdate},
+};
+
+pub async fn handle_ping(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    updates: Vec<MemberUpdate>,
+    storage: &mut Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    storage.apply_member_updates(updates);
+    storage.member_alive(&src);
+    let reply = ReplyBody::PingAck {
+        in_reply_to: msg_id,
+        updates: storage.piggyback(),
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
+
+/// An ack that arrived after its prober gave up still carries news.
+pub async fn handle_ping_ack(
+    src: String,
+    updates: Vec<MemberUpdate>,
+    storage: &mut Storage,
+) -> anyhow::Result<()> {
+    storage.apply_member_updates(updates);
+    storage.member_alive(&src);
+    Ok(())
+}
+
+/// Relay a probe for a node that could not reach `target` itself. Stays
+/// silent if `target` does not answer, so the requester's timeout fires.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_ping_req(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    target: String,
+    updates: Vec<MemberUpdate>,
+    storage: StorageHandle,
+    rpc: RpcClient,
+    tx: Sender<String>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let forwarded = storage
+        .call(move |storage| {
+            storage.apply_member_updates(updates);
+            storage.piggyback()
+        })
+        .await?;
+    let ping = serde_json::json!({ "type": "ping", "updates": forwarded });
+    let Ok(mut ack) = rpc.call(&target, ping, timeout).await else {
+        return Ok(());
+    };
+    let updates: Vec<MemberUpdate> = serde_json::from_value(ack["updates"].take()).unwrap_or_default();
+    let piggyback = storage
+        .call(move |storage| {
+            storage.apply_member_updates(updates);
+            storage.member_alive(&target);
+            storage.piggyback()
+        })
+        .await?;
+
+    let reply = ReplyBody::PingAck {
+        in_reply_to: msg_id,
+        updates: piggyback,
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
@@ -9,7 +9,11 @@ pub async fn handle_read(
     storage: &Storage,
     tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    let messages_vec = storage.values();
+    let messages_vec = if storage.config.total_order_broadcast {
+        storage.total.values()
+    } else {
+        storage.values()
+    };
 
     let reply_body = serde_json::json!({
         "type": "read_ok",
@@ -48,3 +52,33 @@ pub async fn handle_g_counter_read(
 
     Ok(tx.send(json).await?)
 }
+
+pub async fn handle_kv_read(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    key: String,
+    storage: &Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let reply_body = match storage.kv_read(&key) {
+        Ok(value) => serde_json::json!({
+            "type": "read_ok",
+            "in_reply_to": msg_id,
+            "value": value,
+        }),
+        Err(e) => serde_json::to_value(ReplyBody::Error {
+            in_reply_to: msg_id,
+            code: e.code(),
+            text: e.text(),
+        })?,
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply_body,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
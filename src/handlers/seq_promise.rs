@@ -0,0 +1,42 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::{message::ReplyBody, storage::Storage};
+
+pub async fn handle_seq_promise(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    epoch: u64,
+    after: u64,
+    storage: &mut Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let (granted, promised, entries) = storage.promise_epoch(epoch, after);
+    let reply = ReplyBody::SeqPromiseOk {
+        in_reply_to: msg_id,
+        epoch,
+        granted,
+        promised,
+        entries,
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
+
+pub async fn handle_seq_promise_ok(
+    src: String,
+    epoch: u64,
+    granted: bool,
+    promised: u64,
+    entries: Vec<(u64, u64, u64)>,
+    storage: &mut Storage,
+) -> anyhow::Result<()> {
+    storage.promise_received(src, epoch, granted, promised, entries);
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::{message::ReplyBody, storage::Storage};
+
+pub async fn handle_write(
+    src: String,
+    dest: String,
+    msg_id: u64,
+    key: String,
+    value: u64,
+    storage: &mut Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    let reply = match storage.kv_write(key, value) {
+        Ok(()) => ReplyBody::WriteOk {
+            in_reply_to: msg_id,
+        },
+        Err(e) => ReplyBody::Error {
+            in_reply_to: msg_id,
+            code: e.code(),
+            text: e.text(),
+        },
+    };
+    let response = serde_json::json!({
+        "src": dest,
+        "dest": src,
+        "body": reply,
+    });
+    let json = serde_json::to_string(&response)?;
+
+    Ok(tx.send(json).await?)
+}
@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// Bits of a [`Timestamp`] given to the logical counter.
+const LOGICAL_BITS: u32 = 16;
+const MAX_LOGICAL: u64 = (1 << LOGICAL_BITS) - 1;
+
+/// Hybrid logical clock reading: wall-clock milliseconds in the high 48 bits
+/// and a logical counter in the low 16, so plain integer order is HLC order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    pub fn new(physical: u64, logical: u64) -> Self {
+        Self((physical << LOGICAL_BITS) | (logical & MAX_LOGICAL))
+    }
+
+    pub fn physical(self) -> u64 {
+        self.0 >> LOGICAL_BITS
+    }
+
+    pub fn logical(self) -> u64 {
+        self.0 & MAX_LOGICAL
+    }
+
+    /// Smallest timestamp after `self` whose physical part is at least `pt`.
+    fn tick(self, pt: u64) -> Self {
+        if pt > self.physical() {
+            Self::new(pt, 0)
+        } else if self.logical() < MAX_LOGICAL {
+            Self(self.0 + 1)
+        } else {
+            // Counter exhausted: run slightly ahead of the wall clock.
+            Self::new(self.physical() + 1, 0)
+        }
+    }
+}
+
+/// Per-node hybrid logical clock (Kulkarni et al.). Every timestamp it hands
+/// out is greater than any it has issued or received, and stays within
+/// `max_offset_ms` of the local wall clock.
+pub struct Hlc {
+    clock: Arc<dyn Fn() -> u64 + Send + Sync>,
+    last: Mutex<Timestamp>,
+    max_offset_ms: u64,
+}
+
+impl Hlc {
+    pub fn new(clock: Arc<dyn Fn() -> u64 + Send + Sync>, max_offset_ms: u64) -> Self {
+        Self {
+            clock,
+            last: Mutex::new(Timestamp::default()),
+            max_offset_ms,
+        }
+    }
+
+    /// Clock on the system wall time with the default offset bound.
+    pub fn system() -> Self {
+        Self::new(Arc::new(crate::storage::time_now), Config::default().hlc_max_offset_ms)
+    }
+
+    /// Timestamp for a local event or an outgoing message.
+    pub fn now(&self) -> Timestamp {
+        let pt = (self.clock)();
+        let mut last = self.last.lock().unwrap();
+        *last = last.tick(pt);
+        *last
+    }
+
+    /// Merge a timestamp received from another node. Readings too far ahead
+    /// of our wall clock are ignored rather than dragging this node forward.
+    pub fn update(&self, remote: Timestamp) -> Timestamp {
+        let pt = (self.clock)();
+        if remote.physical() > pt + self.max_offset_ms {
+            tracing::warn!(
+                remote = remote.physical(),
+                local = pt,
+                "ignoring hlc timestamp beyond max clock offset"
+            );
+            return self.now();
+        }
+        let mut last = self.last.lock().unwrap();
+        *last = (*last).max(remote).tick(pt);
+        *last
+    }
+
+    /// Latest timestamp issued or observed, without advancing the clock.
+    pub fn peek(&self) -> Timestamp {
+        *self.last.lock().unwrap()
+    }
+
+    /// Attach a fresh timestamp as `body.hlc` if `message` goes to another node.
+    pub fn stamp(&self, message: &mut Value) {
+        if message["dest"].as_str().is_some_and(is_node_id) {
+            message["body"]["hlc"] = self.now().0.into();
+        }
+    }
+
+    /// Merge `body.hlc` from an inbound message, if it carries one.
+    pub fn observe(&self, message: &Value) {
+        if let Some(remote) = message["body"]["hlc"].as_u64() {
+            self.update(Timestamp(remote));
+        }
+    }
+}
+
+/// Maelstrom names cluster nodes `n1`, `n2`, ...; clients and services use
+/// other prefixes and never see our clock.
+pub fn is_node_id(id: &str) -> bool {
+    id.strip_prefix('n')
+        .is_some_and(|rest| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    fn hlc_at(now: &Arc<AtomicU64>) -> Hlc {
+        let now = Arc::clone(now);
+        Hlc::new(Arc::new(move || now.load(Ordering::Relaxed)), 500)
+    }
+
+    #[test]
+    fn stays_monotonic_when_wall_clock_stalls_or_regresses() {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let hlc = hlc_at(&now);
+
+        let a = hlc.now();
+        let b = hlc.now();
+        now.store(900, Ordering::Relaxed);
+        let c = hlc.now();
+        assert!(a < b && b < c);
+        assert_eq!(c.physical(), 1_000);
+        assert_eq!(c.logical(), 2);
+
+        now.store(1_200, Ordering::Relaxed);
+        assert_eq!(hlc.now(), Timestamp::new(1_200, 0));
+    }
+
+    #[test]
+    fn receive_orders_after_sender() {
+        let sender_now = Arc::new(AtomicU64::new(2_000));
+        let receiver_now = Arc::new(AtomicU64::new(1_800));
+        let sender = hlc_at(&sender_now);
+        let receiver = hlc_at(&receiver_now);
+
+        let sent = sender.now();
+        let received = receiver.update(sent);
+        assert!(received > sent);
+        assert!(receiver.now() > sent);
+    }
+
+    #[test]
+    fn ignores_timestamps_beyond_max_offset() {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let hlc = hlc_at(&now);
+
+        let merged = hlc.update(Timestamp::new(10_000, 0));
+        assert_eq!(merged.physical(), 1_000);
+    }
+
+    #[test]
+    fn only_stamps_messages_between_nodes() {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let hlc = hlc_at(&now);
+
+        let mut to_node = serde_json::json!({"src": "n1", "dest": "n2", "body": {"type": "ping"}});
+        let mut to_client = serde_json::json!({"src": "n1", "dest": "c3", "body": {"type": "read_ok"}});
+        let mut to_service = serde_json::json!({"src": "n1", "dest": "seq-kv", "body": {"type": "read"}});
+        hlc.stamp(&mut to_node);
+        hlc.stamp(&mut to_client);
+        hlc.stamp(&mut to_service);
+
+        assert!(to_node["body"]["hlc"].is_u64());
+        assert!(to_client["body"].get("hlc").is_none());
+        assert!(to_service["body"].get("hlc").is_none());
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::IdGenerator;
+
+/// `"{node}-{counter}"`: unique because node names are, but only within one
+/// run of the node.
+pub struct NodeCounterIds {
+    node_id: String,
+    counter: AtomicU64,
+}
+
+impl NodeCounterIds {
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl IdGenerator for NodeCounterIds {
+    fn next_id(&self) -> anyhow::Result<String> {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        Ok(format!("{}-{}", self.node_id, n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_and_monotonic() {
+        let ids = NodeCounterIds::new("n1");
+        let generated: Vec<String> = (0..10_000).map(|_| ids.next_id().unwrap()).collect();
+        assert!(generated.iter().all(|id| id.starts_with("n1-")));
+        let counters: Vec<String> = generated
+            .iter()
+            .map(|id| id.trim_start_matches("n1-").to_string())
+            .collect();
+        super::super::assert_unique_and_monotonic(&counters, true);
+    }
+
+    #[test]
+    fn nodes_do_not_collide() {
+        let a = NodeCounterIds::new("n1");
+        let b = NodeCounterIds::new("n11");
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..100 {
+            assert!(seen.insert(a.next_id().unwrap()));
+            assert!(seen.insert(b.next_id().unwrap()));
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Unique-ID schemes for the `generate` workload.
+
+mod counter;
+mod monotonic;
+mod snowflake;
+mod ulid;
+mod uuid_v7;
+
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{hlc::Hlc, snowflake::Snowflake};
+
+pub use self::{counter::NodeCounterIds, snowflake::SnowflakeIds, ulid::UlidIds, uuid_v7::UuidV7Ids};
+
+/// Source of cluster-unique IDs, rendered as strings for clients. Every
+/// scheme is also monotonic per node: each ID sorts after the previous one
+/// (numerically for snowflake and counter, lexicographically otherwise).
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> anyhow::Result<String>;
+}
+
+/// Which `IdGenerator` a node uses, chosen by `Config::id_scheme`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    #[default]
+    Snowflake,
+    Uuidv7,
+    Ulid,
+    /// `"{node}-{counter}"`.
+    Counter,
+}
+
+impl FromStr for IdScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "snowflake" => Self::Snowflake,
+            "uuidv7" => Self::Uuidv7,
+            "ulid" => Self::Ulid,
+            "counter" => Self::Counter,
+            _ => bail!("Unknown id scheme {}; expected snowflake, uuidv7, ulid or counter", s),
+        })
+    }
+}
+
+impl IdScheme {
+    /// Generator for the node `node_id`, numbered `node_number` in the cluster.
+    pub(crate) fn build(
+        self,
+        node_id: &str,
+        node_number: u64,
+        snowflake: Arc<Snowflake>,
+        hlc: Arc<Hlc>,
+    ) -> Box<dyn IdGenerator> {
+        match self {
+            Self::Snowflake => Box::new(SnowflakeIds::new(snowflake, node_number, hlc)),
+            Self::Uuidv7 => Box::new(UuidV7Ids::new(node_number)),
+            Self::Ulid => Box::new(UlidIds::new(node_number)),
+            Self::Counter => Box::new(NodeCounterIds::new(node_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+fn assert_unique_and_monotonic(ids: &[String], numeric: bool) {
+    let unique: std::collections::HashSet<&String> = ids.iter().collect();
+    assert_eq!(unique.len(), ids.len(), "duplicate ids");
+    for pair in ids.windows(2) {
+        if numeric {
+            let (a, b) = (pair[0].parse::<u64>().unwrap(), pair[1].parse::<u64>().unwrap());
+            assert!(a < b, "{} !< {}", a, b);
+        } else {
+            assert!(pair[0] < pair[1], "{} !< {}", pair[0], pair[1]);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::storage::backoff::xorshift;
+
+/// Millisecond timestamp plus `bits` of randomness, made monotonic the way
+/// the ULID spec suggests: within one millisecond the random part is just
+/// incremented, and if it overflows (or the clock steps back) we carry on
+/// from the last millisecond used instead of waiting.
+pub(super) struct MonotonicRandom {
+    bits: u32,
+    state: Mutex<State>,
+}
+
+struct State {
+    last_ms: u64,
+    random: u128,
+    rng: u64,
+}
+
+impl MonotonicRandom {
+    /// `seed` should differ per node so nodes draw different random parts.
+    pub(super) fn new(bits: u32, seed: u64) -> Self {
+        let rng = now_ms() ^ seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        Self {
+            bits,
+            state: Mutex::new(State {
+                last_ms: 0,
+                random: 0,
+                rng,
+            }),
+        }
+    }
+
+    /// Next (milliseconds, random) pair; strictly increasing as a tuple.
+    pub(super) fn next(&self) -> (u64, u128) {
+        let now = now_ms();
+        let mut state = self.state.lock().unwrap();
+        let max = (1u128 << self.bits) - 1;
+        if now > state.last_ms {
+            state.last_ms = now;
+            // Leave the top bit clear so there is room to increment.
+            state.random = draw(&mut state.rng, self.bits - 1);
+        } else if state.random < max {
+            state.random += 1;
+        } else {
+            state.last_ms += 1;
+            state.random = draw(&mut state.rng, self.bits - 1);
+        }
+        (state.last_ms, state.random)
+    }
+}
+
+fn draw(rng: &mut u64, bits: u32) -> u128 {
+    let high = xorshift(rng) as u128;
+    let low = xorshift(rng) as u128;
+    ((high << 64) | low) & ((1u128 << bits) - 1)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::{hlc::Hlc, snowflake::Snowflake};
+
+use super::IdGenerator;
+
+/// Snowflake integers as decimal strings, sharing the node's generator with
+/// internal message keys so the two never collide.
+pub struct SnowflakeIds {
+    snowflake: Arc<Snowflake>,
+    node_number: u64,
+    hlc: Arc<Hlc>,
+}
+
+impl SnowflakeIds {
+    pub(crate) fn new(snowflake: Arc<Snowflake>, node_number: u64, hlc: Arc<Hlc>) -> Self {
+        Self {
+            snowflake,
+            node_number,
+            hlc,
+        }
+    }
+}
+
+impl IdGenerator for SnowflakeIds {
+    fn next_id(&self) -> anyhow::Result<String> {
+        let id = self
+            .snowflake
+            .try_next_id_after(self.node_number, self.hlc.peek().physical())?;
+        Ok(id.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snowflake::Layout;
+
+    #[test]
+    fn unique_and_monotonic() {
+        let ids = SnowflakeIds::new(Arc::new(Snowflake::new(0, Layout::default(), 1_000)), 3, Arc::new(Hlc::system()));
+        let generated: Vec<String> = (0..10_000).map(|_| ids.next_id().unwrap()).collect();
+        super::super::assert_unique_and_monotonic(&generated, true);
+    }
+}
@@ -0,0 +1,40 @@
+use super::{IdGenerator, monotonic::MonotonicRandom};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// ULIDs: 48-bit millisecond timestamp and 80 random bits, as 26 Crockford
+/// base32 characters that sort in generation order.
+pub struct UlidIds {
+    source: MonotonicRandom,
+}
+
+impl UlidIds {
+    pub fn new(node_number: u64) -> Self {
+        Self {
+            source: MonotonicRandom::new(80, node_number),
+        }
+    }
+}
+
+impl IdGenerator for UlidIds {
+    fn next_id(&self) -> anyhow::Result<String> {
+        let (ms, random) = self.source.next();
+        let value = ((ms as u128) << 80) | random;
+        Ok((0..26)
+            .map(|i| CROCKFORD[((value >> (5 * (25 - i))) & 31) as usize] as char)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_and_monotonic() {
+        let ids = UlidIds::new(1);
+        let generated: Vec<String> = (0..10_000).map(|_| ids.next_id().unwrap()).collect();
+        assert!(generated.iter().all(|id| id.len() == 26));
+        super::super::assert_unique_and_monotonic(&generated, false);
+    }
+}
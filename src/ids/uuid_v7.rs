@@ -0,0 +1,54 @@
+use super::{IdGenerator, monotonic::MonotonicRandom};
+
+/// RFC 9562 version 7 UUIDs: 48-bit millisecond timestamp, version and
+/// variant bits, and 74 bits that are random per millisecond and counted
+/// up within it (the spec's "monotonic random" method).
+pub struct UuidV7Ids {
+    source: MonotonicRandom,
+}
+
+impl UuidV7Ids {
+    pub fn new(node_number: u64) -> Self {
+        Self {
+            source: MonotonicRandom::new(74, node_number),
+        }
+    }
+}
+
+impl IdGenerator for UuidV7Ids {
+    fn next_id(&self) -> anyhow::Result<String> {
+        let (ms, random) = self.source.next();
+        let rand_a = (random >> 62) & 0xfff;
+        let rand_b = random & ((1u128 << 62) - 1);
+        let value = ((ms as u128 & 0xffff_ffff_ffff) << 80) | (0x7 << 76) | (rand_a << 64) | (0b10 << 62) | rand_b;
+        let hex = format!("{:032x}", value);
+        Ok(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_and_monotonic() {
+        let ids = UuidV7Ids::new(1);
+        let generated: Vec<String> = (0..10_000).map(|_| ids.next_id().unwrap()).collect();
+        super::super::assert_unique_and_monotonic(&generated, false);
+    }
+
+    #[test]
+    fn has_version_and_variant_bits() {
+        let id = UuidV7Ids::new(1).next_id().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "7");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::{fmt, time::Duration};
+
+use serde_json::{Value, json};
+
+use crate::rpc::RpcClient;
+
+/// Maelstrom's sequentially consistent key-value service.
+pub const SEQ_KV: &str = "seq-kv";
+
+/// Why a request to a Maelstrom key-value service did not succeed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvClientError {
+    /// Error 20: the key has never been written.
+    KeyDoesNotExist,
+    /// Error 22: a `cas` found a value other than `from`.
+    PreconditionFailed,
+    /// Any other error reply; see `is_definite` for whether it may have applied.
+    Other { code: u64, text: String },
+    /// No reply in time; a write may or may not have happened.
+    Timeout,
+}
+
+impl KvClientError {
+    /// Whether the request is known not to have taken effect.
+    pub fn is_definite(&self) -> bool {
+        match self {
+            Self::KeyDoesNotExist | Self::PreconditionFailed => true,
+            Self::Other { code, .. } => !matches!(code, 0 | 13 | 14),
+            Self::Timeout => false,
+        }
+    }
+}
+
+impl fmt::Display for KvClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyDoesNotExist => write!(f, "key does not exist"),
+            Self::PreconditionFailed => write!(f, "precondition failed"),
+            Self::Other { code, text } => write!(f, "error {}: {}", code, text),
+            Self::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for KvClientError {}
+
+/// Typed `read`/`write`/`cas` over the RPC client for one KV service.
+#[derive(Clone)]
+pub struct KvClient {
+    rpc: RpcClient,
+    service: String,
+    timeout: Duration,
+}
+
+impl KvClient {
+    pub fn new(rpc: RpcClient, service: &str, timeout: Duration) -> Self {
+        Self {
+            rpc,
+            service: service.to_string(),
+            timeout,
+        }
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub async fn read(&self, key: &str) -> Result<u64, KvClientError> {
+        let reply = self.request(json!({"type": "read", "key": key})).await?;
+        reply["value"].as_u64().ok_or_else(|| KvClientError::Other {
+            code: 12,
+            text: format!("read_ok without a numeric value: {}", reply),
+        })
+    }
+
+    pub async fn write(&self, key: &str, value: u64) -> Result<(), KvClientError> {
+        self.request(json!({"type": "write", "key": key, "value": value}))
+            .await
+            .map(drop)
+    }
+
+    pub async fn cas(&self, key: &str, from: u64, to: u64, create_if_not_exists: bool) -> Result<(), KvClientError> {
+        self.request(json!({
+            "type": "cas",
+            "key": key,
+            "from": from,
+            "to": to,
+            "create_if_not_exists": create_if_not_exists,
+        }))
+        .await
+        .map(drop)
+    }
+
+    async fn request(&self, body: Value) -> Result<Value, KvClientError> {
+        let reply = self
+            .rpc
+            .call(&self.service, body, self.timeout)
+            .await
+            .map_err(|e| {
+                tracing::debug!(service = %self.service, error = %e, "kv request failed");
+                KvClientError::Timeout
+            })?;
+        if reply["type"] != "error" {
+            return Ok(reply);
+        }
+        Err(match reply["code"].as_u64() {
+            Some(20) => KvClientError::KeyDoesNotExist,
+            Some(22) => KvClientError::PreconditionFailed,
+            code => KvClientError::Other {
+                code: code.unwrap_or(13),
+                text: reply["text"].as_str().unwrap_or_default().to_string(),
+            },
+        })
+    }
+}
@@ -1,26 +1,44 @@
-use std::io::Write;
-
 use anyhow::Context;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::Sender;
 
 use crate::handlers::add::handle_add;
 use crate::handlers::broadcast::handle_broadcast;
 use crate::handlers::broadcast_ok::handle_broadcast_ok;
+use crate::handlers::cas::handle_cas;
 use crate::handlers::cas_ok::handle_cas_ok;
+use crate::handlers::dump::handle_dump;
 use crate::handlers::echo::handle_echo;
 use crate::handlers::error::handle_error;
 use crate::handlers::id_gen::handle_id_gen;
 use crate::handlers::init::handle_init;
-use crate::handlers::read::{handle_g_counter_read, handle_read};
+use crate::handlers::ping::{handle_ping, handle_ping_ack};
+use crate::handlers::read::{handle_g_counter_read, handle_kv_read, handle_read};
+use crate::handlers::seq_promise::{handle_seq_promise, handle_seq_promise_ok};
 use crate::handlers::topology::handle_topology;
+use crate::handlers::write::handle_write;
 use crate::message::{Body, Message};
-use crate::storage::Storage;
+use crate::storage::{Storage, causal::VectorClock, partition::HashRing};
+use crate::storage::wal::FileBackend;
 
 pub mod broadcast;
+pub mod config;
+pub mod counter;
+pub mod dedupe;
+pub mod dispatch;
+pub mod forward;
 pub mod handlers;
+pub mod hlc;
+pub mod ids;
+pub mod kv;
+pub mod logging;
+pub mod membership;
 pub mod message;
+pub mod rpc;
 pub mod storage;
-mod snowflake;
+pub mod snowflake;
+pub mod writer;
+
+pub use writer::{WriterMetrics, write_stdout};
 
 pub trait Handler {
     /// Handle an incoming message, possibly mutating state, and produce zero or more responses.
@@ -32,9 +50,16 @@ pub async fn process_message_line(
     storage: &mut Storage,
     tx: Sender<String>,
 ) -> anyhow::Result<()> {
-    eprintln!("{}", &line);
-    let msg: Message = serde_json::from_str(&line).expect("Invalid JSON");
+    tracing::trace!(%line, "received");
+    let msg: Message = serde_json::from_str(&line).context("Invalid JSON")?;
+    process_message(msg, storage, tx).await
+}
 
+pub async fn process_message(
+    msg: Message,
+    storage: &mut Storage,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
     // Always handle 'init' globally
 
     let src = msg.src;
@@ -45,22 +70,45 @@ pub async fn process_message_line(
         Body::Init {
             msg_id,
             node_id,
-            node_ids: _,
-            workload: _,
+            node_ids,
+            workload,
         } => {
             storage.set_id(&node_id).await;
+            if workload.is_some() {
+                storage.workload = workload;
+            }
+            storage.assign_node_number(&node_ids)?;
+            storage.install_id_generator();
+            storage.init_members(&node_ids);
+            storage.causal.delivered = VectorClock::over(&node_ids);
+            storage.ring = HashRing::new(&node_ids, storage.config.partition_vnodes);
+            storage.node_ids = node_ids;
+            if let Some(dir) = storage.config.data_dir.clone() {
+                let backend = FileBackend::open(dir.join(&node_id), storage.config.snapshot_every)?;
+                storage.attach_backend(Box::new(backend))?;
+            }
             handle_init(src, dest, msg_id, tx).await
         }
         Body::Add { msg_id, delta } => handle_add(src, dest, msg_id, storage, delta, tx).await,
-        Body::Cas { .. } => Ok(()),
+        Body::Cas {
+            msg_id,
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        } => handle_cas(src, dest, msg_id, key, from, to, create_if_not_exists, storage, tx).await,
         Body::CasOk { in_reply_to } => handle_cas_ok(src, in_reply_to, storage, tx).await,
 
-        Body::Broadcast { msg_id, message } => {
-            handle_broadcast(src, dest, msg_id, storage, message, tx).await
-        }
+        Body::Broadcast {
+            msg_id,
+            message,
+            causal,
+            seq,
+        } => handle_broadcast(src, dest, msg_id, storage, message, causal, seq, tx).await,
         Body::BroadcastOk { in_reply_to } => {
             handle_broadcast_ok(src, dest, in_reply_to, storage).await
         }
+        Body::Dump { msg_id } => handle_dump(src, dest, msg_id, storage, tx).await,
         Body::Echo { msg_id, echo } => handle_echo(src, dest, msg_id, echo, tx).await,
         Body::Error {
             in_reply_to,
@@ -69,12 +117,39 @@ pub async fn process_message_line(
         } => handle_error(in_reply_to, storage).await,
 
         Body::Generate { msg_id } => handle_id_gen(src, dest, msg_id, storage, tx).await,
-        Body::Read { msg_id, key: _, } => match &storage.workload {
+        Body::Ping { msg_id, updates } => {
+            handle_ping(src, dest, msg_id, updates, storage, tx).await
+        }
+        Body::PingAck { updates, .. } => handle_ping_ack(src, updates, storage).await,
+        Body::PingReq { .. } => {
+            // Needs to await another node, so the dispatcher always runs it
+            // off the actor; reaching here is a routing bug.
+            tracing::error!("ping_req reached the storage actor; ignoring");
+            Ok(())
+        }
+        Body::Read {
+            msg_id,
+            key: Some(key),
+        } => handle_kv_read(src, dest, msg_id, key, storage, tx).await,
+        Body::Read { msg_id, key: None } => match &storage.workload {
             Some(workload) if workload == "g-counter" => {
                 handle_g_counter_read(src, dest, msg_id, storage, tx).await
             }
             _ => handle_read(src, dest, msg_id, storage, tx).await,
         },
+        Body::Write { msg_id, key, value } => {
+            handle_write(src, dest, msg_id, key, value, storage, tx).await
+        }
+        Body::SeqPromise { msg_id, epoch, after } => {
+            handle_seq_promise(src, dest, msg_id, epoch, after, storage, tx).await
+        }
+        Body::SeqPromiseOk {
+            epoch,
+            granted,
+            promised,
+            entries,
+            ..
+        } => handle_seq_promise_ok(src, epoch, granted, promised, entries, storage).await,
         Body::Topology { msg_id, topology } => {
             let node_id = &storage
                 .node_id
@@ -88,17 +163,3 @@ pub async fn process_message_line(
         }
     }
 }
-
-pub async fn write_stdout<W: Write>(mut writer: W, mut rx: Receiver<String>) -> anyhow::Result<()> {
-    while let Some(msg) = rx.recv().await {
-        eprintln!("{}", &msg);
-        if let Err(e) = writeln!(writer, "{}", msg).context("Failed to output to stdout") {
-            eprintln!(
-                "Writer error ({}), shutting down response handler for msg: {:?}",
-                e, msg
-            );
-            break;
-        }
-    }
-    Ok(())
-}
@@ -0,0 +1,16 @@
+use tracing_subscriber::{EnvFilter, fmt};
+
+/// Env var holding the filter directives, e.g. `info,maelstrom_rust_node::storage=debug`.
+pub const LOG_ENV: &str = "MAELSTROM_LOG";
+
+/// Install the global subscriber. Maelstrom reads stdout, so everything goes
+/// to stderr, which ends up in the per-node log file.
+pub fn init() {
+    let filter = EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new("info"));
+    fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .compact()
+        .init();
+}
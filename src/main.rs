@@ -1,73 +1,218 @@
-use std::{
-    io::{self},
-    sync::Arc,
-};
+use std::sync::Arc;
 
+use anyhow::Context;
 use maelstrom_rust_node::{
     broadcast::actor::broadcast_message,
-    process_message_line,
-    storage::{value_store::spawn_gossip_sender, Storage},
-    write_stdout,
+    config::Config,
+    dispatch::Dispatcher,
+    logging,
+    membership::run_prober,
+    rpc::RpcClient,
+    storage::{
+        Storage,
+        actor::{StorageHandle, run_storage},
+        value_store::spawn_gossip_sender,
+    },
+    WriterMetrics, write_stdout,
 };
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
-    sync::{Mutex, mpsc},
+    signal::unix::{SignalKind, signal},
+    sync::mpsc,
 };
+use tokio_util::sync::CancellationToken;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let (tx, rx) = mpsc::channel(1024);
-    let (gossip_sender, gossip_receiver) = mpsc::channel(1024);
+    logging::init();
+    let config = Config::load()?;
+    tracing::info!(?config, "starting");
+    let (tx, rx) = mpsc::channel(config.channel_capacity);
+    let (gossip_sender, gossip_receiver) = mpsc::channel(config.channel_capacity);
+    let shutdown = CancellationToken::new();
+
+    let mut storage = Storage::new_with_config(gossip_sender.clone(), config.clone())?;
+    if let Some(path) = &config.snapshot {
+        let json = std::fs::read_to_string(path).context("Failed to read snapshot")?;
+        storage.load_snapshot_json(&json).await?;
+    }
+    let node_id_arc = storage.node_id.clone();
+    let hlc = storage.hlc.clone();
+    let (storage_handle, storage_rx) = StorageHandle::new(config.channel_capacity);
+    let storage_task = tokio::spawn(run_storage(storage, storage_rx, tx.clone()));
+
+    let rpc = RpcClient::new(node_id_arc.clone(), tx.clone(), hlc.clone());
+    let dispatcher = Dispatcher::new(
+        storage_handle.clone(),
+        rpc.clone(),
+        tx.clone(),
+        &config,
+    );
 
-    let tx_read = tx.clone();
-    let storage = Arc::new(Mutex::new(Storage::new(gossip_sender.clone())));
-    let storage_read = Arc::clone(&storage);
-    let node_id_arc = Arc::clone(&storage_read.lock().await.node_id);
     let read_stdin_task = {
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
+            // EOF, a read error or a panic all mean Maelstrom is done with us.
+            let _shutdown_guard = shutdown.clone().drop_guard();
             let stdin = tokio::io::stdin();
             let reader = BufReader::new(stdin);
             let mut lines = reader.lines();
 
-            while let Some(line_res) = lines.next_line().await.transpose() {
-                let line = line_res.expect("Failed to read line");
-                let mut storage_guard = storage_read.lock().await;
-                _ = process_message_line(line, &mut *storage_guard, tx_read.clone()).await;
+            loop {
+                let line = tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    line = lines.next_line() => line,
+                };
+                match line {
+                    Ok(Some(line)) => {
+                        if let Err(e) = dispatcher.dispatch(line).await {
+                            tracing::warn!(error = %e, "failed to dispatch message");
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::error!(error = %e, "failed to read stdin");
+                        break;
+                    }
+                }
             }
         })
     };
 
+    let writer_metrics = Arc::new(WriterMetrics::default());
     let write_stdout_task = {
+        let hlc = hlc.clone();
+        let metrics = Arc::clone(&writer_metrics);
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let stdout = tokio::io::stdout();
+
+            let result = write_stdout(stdout, rx, &hlc, &metrics).await;
+            // Nothing more can reach Maelstrom, so stop accepting work.
+            shutdown.cancel();
+            result
+        })
+    };
+
+    {
+        let shutdown = shutdown.clone();
+        let mut sigterm = signal(SignalKind::terminate()).context("Failed to install SIGTERM handler")?;
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = sigterm.recv() => shutdown.cancel(),
+                _ = shutdown.cancelled() => {}
+            }
+        });
+    }
+
+    {
+        let storage = storage_handle.clone();
+        let metrics = Arc::clone(&writer_metrics);
+        let shutdown = shutdown.clone();
+        let mut sigusr1 =
+            signal(SignalKind::user_defined1()).context("Failed to install SIGUSR1 handler")?;
         tokio::spawn(async move {
-            let stdout = io::stdout();
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = sigusr1.recv() => {}
+                }
+                match storage.snapshot_json().await {
+                    Ok(json) => tracing::info!(snapshot = %json, "state dump"),
+                    Err(e) => tracing::error!(error = %e, "failed to serialize snapshot"),
+                }
+                tracing::info!(writer = ?metrics.stats(), "writer metrics");
+                if let Ok(borrowed) = storage.call(|storage| storage.snowflake.borrowed()).await {
+                    tracing::info!(borrowed, "snowflake ids issued ahead of the clock");
+                }
+            }
+        });
+    }
 
-            _ = write_stdout(stdout, rx).await;
+    let prober = {
+        let storage = storage_handle.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        let id = node_id_arc.clone();
+        tokio::spawn(run_prober(storage, rpc, id, config, shutdown))
+    };
+
+    let gossip_sender = {
+        let storage = storage_handle.clone();
+        let id = node_id_arc.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            spawn_gossip_sender(storage, gossip_sender, id, &config, shutdown).await;
         })
     };
+    let broadcast_message_sender = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { broadcast_message(gossip_receiver, tx, node_id_arc, shutdown).await })
+    };
 
-    let gossip_sender = tokio::spawn(async move {
-        spawn_gossip_sender(Arc::clone(&storage), gossip_sender).await;
-    });
-    let broadcast_message_sender = tokio::spawn(async move {
-        let _ = broadcast_message(gossip_receiver, tx, node_id_arc).await;
-    });
+    drop(storage_handle);
 
-    let (reader_result, writer_result, _, _) = tokio::join!(
+    // The storage actor flushes and exits once the reader, gossip and signal
+    // tasks drop their handles; the writer finishes once every outbound sender
+    // is dropped, i.e. after the storage and broadcast actors have drained.
+    let (reader_result, writer_result, prober_result, gossip_result, broadcast_result, storage_result) = tokio::join!(
         read_stdin_task,
         write_stdout_task,
+        prober,
         gossip_sender,
-        broadcast_message_sender
+        broadcast_message_sender,
+        storage_task
     );
 
     // Handle results properly
-    match reader_result {
-        Result::Ok(()) => {}
-
-        Err(e) => eprintln!("Reader task panicked: {:?}", e),
+    let mut failed = false;
+    if let Err(e) = reader_result {
+        tracing::error!(error = ?e, "reader task panicked");
+        failed = true;
+    }
+    if let Err(e) = prober_result {
+        tracing::error!(error = ?e, "prober task panicked");
+        failed = true;
+    }
+    if let Err(e) = gossip_result {
+        tracing::error!(error = ?e, "gossip task panicked");
+        failed = true;
+    }
+    match broadcast_result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "broadcast actor failed");
+            failed = true;
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, "broadcast task panicked");
+            failed = true;
+        }
     }
-
     match writer_result {
-        Result::Ok(()) => {}
-        Err(e) => eprintln!("Writer task panicked: {:?}", e),
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "writer failed");
+            failed = true;
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, "writer task panicked");
+            failed = true;
+        }
+    }
+    match storage_result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "failed to flush state");
+            failed = true;
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, "storage task panicked");
+            failed = true;
+        }
+    }
+    if failed {
+        anyhow::bail!("node shut down with errors");
     }
     Ok(())
 }
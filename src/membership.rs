@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    config::Config,
+    rpc::RpcClient,
+    storage::{NodeId, actor::StorageHandle, membership::MemberUpdate, wait_for_node_id},
+};
+
+/// SWIM failure detection: each period, ping one member; if it does not ack
+/// in time, ask a few others to ping it for us; if none of them hear back
+/// either, start suspecting it. Membership changes ride along on every
+/// ping and ack. Returns straight away unless `Config::swim_enabled` is set.
+pub async fn run_prober(
+    storage: StorageHandle,
+    rpc: RpcClient,
+    id: NodeId,
+    config: Config,
+    shutdown: CancellationToken,
+) {
+    if !config.swim_enabled {
+        return;
+    }
+    let Some(id) = wait_for_node_id(&id, &shutdown).await else {
+        return;
+    };
+    let span = tracing::info_span!("prober", node = %id);
+    async {
+        let mut interval = tokio::time::interval(config.swim_probe_interval());
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+            let Ok(next) = storage.call(|storage| storage.next_probe()).await else {
+                return;
+            };
+            let Some((target, updates)) = next else {
+                continue;
+            };
+            let (storage, rpc, config) = (storage.clone(), rpc.clone(), config.clone());
+            // Probes can take up to a full period; don't let them delay the next one.
+            tokio::spawn(
+                async move {
+                    if let Err(e) = probe(&storage, &rpc, &config, target, updates).await {
+                        tracing::debug!(error = %e, "probe aborted");
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+async fn probe(
+    storage: &StorageHandle,
+    rpc: &RpcClient,
+    config: &Config,
+    target: String,
+    updates: Vec<MemberUpdate>,
+) -> anyhow::Result<()> {
+    let ping = serde_json::json!({ "type": "ping", "updates": updates });
+    if let Ok(ack) = rpc.call(&target, ping, config.swim_probe_timeout()).await {
+        return record_ack(storage, target, ack).await;
+    }
+
+    let k = config.swim_indirect_probes;
+    let helper_target = target.clone();
+    let (helpers, updates) = storage
+        .call(move |storage| {
+            let helpers = storage.indirect_helpers(&helper_target, k);
+            (helpers, storage.piggyback())
+        })
+        .await?;
+    let remaining = config
+        .swim_probe_interval()
+        .saturating_sub(config.swim_probe_timeout())
+        .max(Duration::from_millis(1));
+
+    let mut requests = JoinSet::new();
+    for helper in helpers {
+        let rpc = rpc.clone();
+        let body = serde_json::json!({ "type": "ping_req", "target": target, "updates": updates });
+        requests.spawn(async move { rpc.call(&helper, body, remaining).await });
+    }
+    while let Some(result) = requests.join_next().await {
+        if let Ok(Ok(ack)) = result {
+            return record_ack(storage, target, ack).await;
+        }
+    }
+
+    storage
+        .call(move |storage| storage.suspect_member(&target))
+        .await
+}
+
+async fn record_ack(storage: &StorageHandle, target: String, mut ack: serde_json::Value) -> anyhow::Result<()> {
+    let updates: Vec<MemberUpdate> = serde_json::from_value(ack["updates"].take()).unwrap_or_default();
+    storage
+        .call(move |storage| {
+            storage.apply_member_updates(updates);
+            storage.member_alive(&target);
+        })
+        .await
+}
@@ -1,6 +1,10 @@
 use std::{collections::HashMap};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::storage::{
+    causal::CausalTag, membership::MemberUpdate, snapshot::NodeSnapshot, total_order::Sequenced,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct Message {
@@ -37,27 +41,86 @@ pub enum Body {
     #[serde(rename = "add")]
     Add { msg_id: u64, delta: u64 },
     #[serde(rename = "broadcast")]
-    Broadcast { msg_id: u64, message: BroadcastMessage },
+    Broadcast {
+        msg_id: u64,
+        message: BroadcastMessage,
+        /// Present between nodes in causal-broadcast mode.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        causal: Option<CausalTag>,
+        /// Position assigned by the sequencer in total-order mode.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<Sequenced>,
+    },
     #[serde(rename = "broadcast_ok")]
     BroadcastOk { in_reply_to: u64 },
+    /// Debug request: reply with the node's full state as a snapshot.
+    #[serde(rename = "dump")]
+    Dump { msg_id: u64 },
     #[serde(rename = "echo")]
     Echo { msg_id: u64, echo: String },
     #[serde(rename = "error")]
     Error { in_reply_to: u64, code: u64, text: String,  },
     #[serde(rename = "generate")]
     Generate { msg_id: u64 },
+    /// SWIM probe between nodes.
+    #[serde(rename = "ping")]
+    Ping {
+        msg_id: u64,
+        #[serde(default)]
+        updates: Vec<MemberUpdate>,
+    },
+    #[serde(rename = "ping_ack")]
+    PingAck {
+        in_reply_to: u64,
+        #[serde(default)]
+        updates: Vec<MemberUpdate>,
+    },
+    /// Ask the receiver to ping `target` on our behalf.
+    #[serde(rename = "ping_req")]
+    PingReq {
+        msg_id: u64,
+        target: String,
+        #[serde(default)]
+        updates: Vec<MemberUpdate>,
+    },
     #[serde(rename = "read")]
-    Read { msg_id: u64, key: Option<String> },
+    Read {
+        msg_id: u64,
+        #[serde(default, deserialize_with = "optional_key")]
+        key: Option<String>,
+    },
+    #[serde(rename = "write")]
+    Write {
+        msg_id: u64,
+        #[serde(deserialize_with = "key")]
+        key: String,
+        value: u64,
+    },
     #[serde(rename = "cas")]
     Cas {
         msg_id: u64,
+        #[serde(deserialize_with = "key")]
         key: String,
         from: u64,
         to: u64,
+        #[serde(default)]
         create_if_not_exists: bool,
     },
     #[serde(rename = "cas_ok")]
     CasOk { in_reply_to: u64 },
+    /// A would-be sequencer asking for a promise to ignore older epochs.
+    #[serde(rename = "seq_promise")]
+    SeqPromise { msg_id: u64, epoch: u64, after: u64 },
+    #[serde(rename = "seq_promise_ok")]
+    SeqPromiseOk {
+        in_reply_to: u64,
+        epoch: u64,
+        granted: bool,
+        /// The epoch the sender has promised, when it refused.
+        promised: u64,
+        /// Sequenced values after the requested point, as (seq, epoch, value).
+        entries: Vec<(u64, u64, u64)>,
+    },
     #[serde(rename = "topology")]
     Topology {
         msg_id: u64,
@@ -81,17 +144,70 @@ pub enum ReplyBody {
     AddOk { in_reply_to: u64 },
     #[serde(rename = "broadcast_ok")]
     BroadcastOk { in_reply_to: u64 },
+    #[serde(rename = "cas_ok")]
+    CasOk { in_reply_to: u64 },
+    #[serde(rename = "dump_ok")]
+    DumpOk {
+        in_reply_to: u64,
+        snapshot: Box<NodeSnapshot>,
+    },
+    #[serde(rename = "error")]
+    Error {
+        in_reply_to: u64,
+        code: u64,
+        text: String,
+    },
     #[serde(rename = "init_ok")]
     InitOk { in_reply_to: u64 },
     #[serde(rename = "echo_ok")]
     EchoOk { in_reply_to: u64, echo: String },
     #[serde(rename = "generate_ok")]
     GenerateOk { id: String, in_reply_to: u64 },
+    #[serde(rename = "ping_ack")]
+    PingAck {
+        in_reply_to: u64,
+        updates: Vec<MemberUpdate>,
+    },
     #[serde(rename = "read_ok")]
     ReadOk {
         in_reply_to: u64,
         messages: ReadMessage,
     },
+    #[serde(rename = "seq_promise_ok")]
+    SeqPromiseOk {
+        in_reply_to: u64,
+        epoch: u64,
+        granted: bool,
+        promised: u64,
+        entries: Vec<(u64, u64, u64)>,
+    },
     #[serde(rename = "topology_ok")]
     TopologyOk { in_reply_to: u64 },
+    #[serde(rename = "write_ok")]
+    WriteOk { in_reply_to: u64 },
+}
+
+/// Maelstrom's kv workloads send integer keys; normalise them to strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawKey {
+    Text(String),
+    Number(u64),
+}
+
+impl From<RawKey> for String {
+    fn from(raw: RawKey) -> Self {
+        match raw {
+            RawKey::Text(key) => key,
+            RawKey::Number(key) => key.to_string(),
+        }
+    }
+}
+
+fn key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    RawKey::deserialize(deserializer).map(String::from)
+}
+
+fn optional_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Option::<RawKey>::deserialize(deserializer).map(|raw| raw.map(String::from))
 }
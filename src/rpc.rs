@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use serde_json::Value;
+use tokio::sync::{mpsc::Sender, oneshot};
+
+use crate::{hlc::Hlc, storage::NodeId};
+
+/// Sends requests to other nodes or services and resolves their replies.
+///
+/// Replies are matched on `in_reply_to` by the dispatcher before normal
+/// handling, so a handler awaiting `call` never blocks the storage actor.
+#[derive(Clone)]
+pub struct RpcClient {
+    node_id: NodeId,
+    tx: Sender<String>,
+    hlc: Arc<Hlc>,
+    next_msg_id: Arc<AtomicU64>,
+    pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>,
+}
+
+impl RpcClient {
+    pub fn new(node_id: NodeId, tx: Sender<String>, hlc: Arc<Hlc>) -> Self {
+        Self {
+            node_id,
+            tx,
+            hlc,
+            next_msg_id: Arc::new(AtomicU64::new(1)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Send `body` to `dest` with a fresh `msg_id` and wait for the reply body.
+    pub async fn call(&self, dest: &str, mut body: Value, timeout: Duration) -> anyhow::Result<Value> {
+        let src = self.node_id().await?;
+        let msg_id = self.next_msg_id.fetch_add(1, Ordering::Relaxed);
+        body["msg_id"] = msg_id.into();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(msg_id, reply_tx);
+
+        let request = serde_json::json!({
+            "src": src,
+            "dest": dest,
+            "body": body,
+        });
+        if let Err(e) = self.tx.send(serde_json::to_string(&request)?).await {
+            self.pending.lock().unwrap().remove(&msg_id);
+            return Err(e.into());
+        }
+
+        match tokio::time::timeout(timeout, reply_rx).await {
+            Ok(reply) => Ok(reply?),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&msg_id);
+                anyhow::bail!("RPC {} to {} timed out", msg_id, dest)
+            }
+        }
+    }
+
+    /// This node's id, once `init` has set it.
+    pub async fn node_id(&self) -> anyhow::Result<String> {
+        self.node_id
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Node id has not been set"))
+    }
+
+    pub fn hlc(&self) -> &Arc<Hlc> {
+        &self.hlc
+    }
+
+    /// Hand `message` to the caller waiting on its `in_reply_to`.
+    /// Gives the message back if nobody is waiting for it.
+    pub fn complete(&self, message: Value) -> Result<(), Value> {
+        let Some(in_reply_to) = message["body"]["in_reply_to"].as_u64() else {
+            return Err(message);
+        };
+        let Some(waiter) = self.pending.lock().unwrap().remove(&in_reply_to) else {
+            return Err(message);
+        };
+        let mut message = message;
+        let _ = waiter.send(message["body"].take());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn call_resolves_with_matching_reply() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let rpc = RpcClient::new(Arc::new(tokio::sync::Mutex::new(Some("n1".into()))), tx, Arc::new(Hlc::system()));
+
+        let caller = {
+            let rpc = rpc.clone();
+            tokio::spawn(async move {
+                rpc.call(
+                    "seq-kv",
+                    serde_json::json!({"type": "read", "key": "k"}),
+                    Duration::from_secs(1),
+                )
+                .await
+            })
+        };
+
+        let request: Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(request["dest"], "seq-kv");
+        let msg_id = request["body"]["msg_id"].as_u64().unwrap();
+
+        let unrelated = serde_json::json!({"src": "seq-kv", "dest": "n1", "body": {"type": "read_ok", "in_reply_to": msg_id + 1}});
+        assert!(rpc.complete(unrelated).is_err());
+
+        let reply = serde_json::json!({"src": "seq-kv", "dest": "n1", "body": {"type": "read_ok", "in_reply_to": msg_id, "value": 3}});
+        assert!(rpc.complete(reply).is_ok());
+
+        let body = caller.await.unwrap().unwrap();
+        assert_eq!(body["value"], 3);
+    }
+
+    #[tokio::test]
+    async fn call_times_out_without_reply() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let rpc = RpcClient::new(Arc::new(tokio::sync::Mutex::new(Some("n1".into()))), tx, Arc::new(Hlc::system()));
+
+        let result = rpc
+            .call("seq-kv", serde_json::json!({"type": "read"}), Duration::from_millis(10))
+            .await;
+
+        assert!(result.is_err());
+        assert!(rpc.pending.lock().unwrap().is_empty());
+    }
+}
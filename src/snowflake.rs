@@ -1,62 +1,219 @@
 use std::{
-    sync::Mutex,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{SystemTime, UNIX_EPOCH},
 };
 
-const CUSTOM_EPOCH: u64 = 1577836800000; // Jan 1 2020 UTC in ms
-const NODE_BITS: u64 = 10;
-const SEQ_BITS: u64 = 12;
+use std::fmt;
 
-const MAX_NODE_ID: u64 = (1 << NODE_BITS) - 1;
-const MAX_SEQ: u64 = (1 << SEQ_BITS) - 1;
+use anyhow::bail;
 
+/// How the 64 bits of an ID are split: from the top, milliseconds since the
+/// epoch, then the node number, then a per-millisecond sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub node_bits: u32,
+    pub sequence_bits: u32,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            node_bits: 10,
+            sequence_bits: 12,
+        }
+    }
+}
+
+impl Layout {
+    /// Rejects layouts that leave fewer than 41 timestamp bits (about 70
+    /// years of milliseconds) or have no room for sequence or node.
+    pub fn new(node_bits: u32, sequence_bits: u32) -> anyhow::Result<Self> {
+        if node_bits == 0 || sequence_bits == 0 || node_bits + sequence_bits > 23 {
+            bail!(
+                "Snowflake layout of {} node and {} sequence bits must leave at least 41 timestamp bits",
+                node_bits,
+                sequence_bits
+            );
+        }
+        Ok(Self {
+            node_bits,
+            sequence_bits,
+        })
+    }
+
+    pub fn max_node(&self) -> u64 {
+        (1 << self.node_bits) - 1
+    }
+
+    pub fn max_sequence(&self) -> u64 {
+        (1 << self.sequence_bits) - 1
+    }
+
+    fn timestamp_shift(&self) -> u32 {
+        self.node_bits + self.sequence_bits
+    }
+}
+
+/// The fields an ID was built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnowflakeParts {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub node: u64,
+    pub sequence: u64,
+}
+
+impl fmt::Display for SnowflakeParts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node {} at {}ms seq {}", self.node, self.timestamp_ms, self.sequence)
+    }
+}
+
+/// Split `id` back into its fields, given the epoch and layout it was made with.
+pub fn decode(id: u64, epoch: u64, layout: Layout) -> SnowflakeParts {
+    SnowflakeParts {
+        timestamp_ms: (id >> layout.timestamp_shift()) + epoch,
+        node: (id >> layout.sequence_bits) & layout.max_node(),
+        sequence: id & layout.max_sequence(),
+    }
+}
+
+/// Snowflake ID generator. When the wall clock goes backwards, or a
+/// millisecond's sequence runs out, it keeps counting on a logical
+/// millisecond ahead of the clock instead of waiting for time to catch up.
 pub struct Snowflake {
+    epoch: u64,
+    layout: Layout,
+    max_borrow_ms: u64,
+    clock: Arc<dyn Fn() -> u64 + Send + Sync>,
     inner: Mutex<SnowflakeState>,
+    borrowed: AtomicU64,
 }
 
+#[derive(Clone, Copy)]
 struct SnowflakeState {
     last_ts: u64,
     sequence: u64,
 }
 
+impl SnowflakeState {
+    /// State after issuing one more ID with the wall clock at `now`.
+    fn advance(self, now: u64, max_sequence: u64) -> Self {
+        if now > self.last_ts {
+            Self {
+                last_ts: now,
+                sequence: 0,
+            }
+        } else if self.sequence < max_sequence {
+            Self {
+                last_ts: self.last_ts,
+                sequence: self.sequence + 1,
+            }
+        } else {
+            Self {
+                last_ts: self.last_ts + 1,
+                sequence: 0,
+            }
+        }
+    }
+}
+
 impl Snowflake {
-    pub fn new() -> Self {
+    pub fn new(epoch: u64, layout: Layout, max_borrow_ms: u64) -> Self {
+        Self::with_clock(epoch, layout, max_borrow_ms, Arc::new(current_time_ms))
+    }
+
+    pub fn with_clock(
+        epoch: u64,
+        layout: Layout,
+        max_borrow_ms: u64,
+        clock: Arc<dyn Fn() -> u64 + Send + Sync>,
+    ) -> Self {
         Self {
+            epoch,
+            layout,
+            max_borrow_ms,
+            clock,
             inner: Mutex::new(SnowflakeState {
                 last_ts: 0,
                 sequence: 0,
             }),
+            borrowed: AtomicU64::new(0),
         }
     }
 
     pub fn next_id(&self, node_id: u64) -> u64 {
-        let mut state = self.inner.lock().unwrap();
+        self.next_id_after(node_id, 0)
+    }
 
-        let mut ts = current_time_ms();
+    /// Like `next_id`, but treats the current time as at least `floor_ms`,
+    /// e.g. the latest hybrid clock reading from a peer running ahead.
+    /// Never fails: borrows as far ahead of the clock as it has to.
+    pub fn next_id_after(&self, node_id: u64, floor_ms: u64) -> u64 {
+        let now = (self.clock)().max(floor_ms);
+        let mut state = self.inner.lock().unwrap();
+        *state = state.advance(now, self.layout.max_sequence());
+        self.compose(node_id, now, *state)
+    }
 
-        if ts < state.last_ts {
-            // Clock went backwards — wait until last_ts or panic
-            ts = wait_next_ms(state.last_ts);
+    /// Like `next_id_after`, but refuses to run more than `max_borrow_ms`
+    /// ahead of the clock, e.g. after a large backwards clock step.
+    pub fn try_next_id_after(&self, node_id: u64, floor_ms: u64) -> anyhow::Result<u64> {
+        let now = (self.clock)().max(floor_ms);
+        let mut state = self.inner.lock().unwrap();
+        let next = state.advance(now, self.layout.max_sequence());
+        if next.last_ts > now + self.max_borrow_ms {
+            bail!(
+                "Clock is {}ms behind the last issued ID, more than the {}ms allowed",
+                next.last_ts - now,
+                self.max_borrow_ms
+            );
         }
+        *state = next;
+        Ok(self.compose(node_id, now, next))
+    }
 
-        if ts == state.last_ts {
-            state.sequence += 1;
-            if state.sequence > MAX_SEQ {
-                // Sequence overflow in same ms — wait next ms
-                ts = wait_next_ms(state.last_ts);
-                state.sequence = 0;
-                state.last_ts = ts;
-            }
-        } else {
-            // New timestamp, reset sequence
-            state.sequence = 0;
-            state.last_ts = ts;
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Fields of an ID issued by this generator (or one with the same
+    /// epoch and layout).
+    pub fn decode(&self, id: u64) -> SnowflakeParts {
+        decode(id, self.epoch, self.layout)
+    }
+
+    /// Timestamp of the most recently issued ID.
+    pub fn last_timestamp(&self) -> u64 {
+        self.inner.lock().unwrap().last_ts
+    }
+
+    /// Continue strictly after any ID with a timestamp up to `until_ms`,
+    /// e.g. a high-water mark persisted before a restart.
+    pub fn resume_after(&self, until_ms: u64) {
+        let mut state = self.inner.lock().unwrap();
+        if until_ms >= state.last_ts {
+            state.last_ts = until_ms;
+            state.sequence = self.layout.max_sequence();
         }
+    }
+
+    /// How many IDs were issued with a timestamp ahead of the clock.
+    pub fn borrowed(&self) -> u64 {
+        self.borrowed.load(Ordering::Relaxed)
+    }
 
+    fn compose(&self, node_id: u64, now: u64, state: SnowflakeState) -> u64 {
+        if state.last_ts > now {
+            self.borrowed.fetch_add(1, Ordering::Relaxed);
+        }
         // Compose ID: timestamp | node_id | sequence
-        ((ts - CUSTOM_EPOCH) << (NODE_BITS + SEQ_BITS))
-            | ((node_id & MAX_NODE_ID) << SEQ_BITS)
-            | (state.sequence & MAX_SEQ)
+        (state.last_ts.saturating_sub(self.epoch) << self.layout.timestamp_shift())
+            | ((node_id & self.layout.max_node()) << self.layout.sequence_bits)
+            | (state.sequence & self.layout.max_sequence())
     }
 }
 
@@ -67,10 +224,85 @@ fn current_time_ms() -> u64 {
         .as_millis() as u64
 }
 
-fn wait_next_ms(last_ts: u64) -> u64 {
-    let mut ts = current_time_ms();
-    while ts <= last_ts {
-        ts = current_time_ms();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snowflake_at(now: &Arc<AtomicU64>, max_borrow_ms: u64) -> Snowflake {
+        let now = Arc::clone(now);
+        Snowflake::with_clock(0, Layout::default(), max_borrow_ms, Arc::new(move || now.load(Ordering::Relaxed)))
+    }
+
+    #[test]
+    fn clock_regression_borrows_instead_of_waiting() {
+        let now = Arc::new(AtomicU64::new(10_000));
+        let snowflake = snowflake_at(&now, 1_000);
+
+        let before = snowflake.next_id(1);
+        now.store(9_000, Ordering::Relaxed);
+        let after = snowflake.next_id(1);
+
+        assert!(after > before);
+        assert_eq!(snowflake.borrowed(), 1);
+    }
+
+    #[test]
+    fn sequence_overflow_moves_to_the_next_millisecond() {
+        let now = Arc::new(AtomicU64::new(10_000));
+        let snowflake = snowflake_at(&now, 1_000);
+
+        let max_sequence = Layout::default().max_sequence();
+        let ids: Vec<u64> = (0..=max_sequence + 1).map(|_| snowflake.next_id(1)).collect();
+
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(snowflake.decode(*ids.last().unwrap()).timestamp_ms, 10_001);
+        assert_eq!(snowflake.borrowed(), 1);
+    }
+
+    #[test]
+    fn bounded_generation_refuses_to_borrow_too_far() {
+        let now = Arc::new(AtomicU64::new(10_000));
+        let snowflake = snowflake_at(&now, 100);
+
+        let before = snowflake.try_next_id_after(1, 0).unwrap();
+        now.store(9_950, Ordering::Relaxed);
+        assert!(snowflake.try_next_id_after(1, 0).unwrap() > before);
+        now.store(5_000, Ordering::Relaxed);
+        assert!(snowflake.try_next_id_after(1, 0).is_err());
+        // Once the clock catches up, IDs flow again.
+        now.store(10_001, Ordering::Relaxed);
+        assert!(snowflake.try_next_id_after(1, 0).is_ok());
+    }
+
+    #[test]
+    fn decode_recovers_fields_for_custom_layouts() {
+        let now = Arc::new(AtomicU64::new(1_700_000_000_000));
+        let clock = Arc::clone(&now);
+        for layout in [Layout::default(), Layout::new(16, 6).unwrap(), Layout::new(4, 8).unwrap()] {
+            let clock = Arc::clone(&clock);
+            let snowflake = Snowflake::with_clock(
+                1_600_000_000_000,
+                layout,
+                1_000,
+                Arc::new(move || clock.load(Ordering::Relaxed)),
+            );
+            snowflake.next_id(layout.max_node());
+            let parts = snowflake.decode(snowflake.next_id(layout.max_node()));
+            assert_eq!(
+                parts,
+                SnowflakeParts {
+                    timestamp_ms: 1_700_000_000_000,
+                    node: layout.max_node(),
+                    sequence: 1,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn layouts_must_leave_room_for_time() {
+        assert!(Layout::new(16, 8).is_err());
+        assert!(Layout::new(0, 12).is_err());
+        assert!(Layout::new(11, 12).is_ok());
     }
-    ts
 }
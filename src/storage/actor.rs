@@ -0,0 +1,178 @@
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    oneshot,
+};
+
+use tracing::{Instrument, Span};
+
+use crate::{broadcast::actor::BroadcastCommand, message::Message, process_message};
+
+use super::Storage;
+
+pub enum StorageCommand {
+    /// An inbound message whose handler needs `Storage`, the span to run it
+    /// in, and where its output goes if not the actor's usual sender.
+    Message(Box<Message>, Span, Option<Sender<String>>),
+    /// Collect values still awaiting an ack from online (or offline) peers.
+    Gossip {
+        online: bool,
+        reply: oneshot::Sender<Vec<BroadcastCommand>>,
+    },
+    Snapshot {
+        reply: oneshot::Sender<anyhow::Result<String>>,
+    },
+    /// Run a closure against `Storage`; used by background tasks that need a
+    /// few reads and writes without a dedicated command.
+    Call(Box<dyn FnOnce(&mut Storage) + Send>),
+}
+
+/// Cheap, cloneable access to the task that owns `Storage`.
+#[derive(Clone)]
+pub struct StorageHandle {
+    tx: Sender<StorageCommand>,
+}
+
+impl StorageHandle {
+    pub fn new(capacity: usize) -> (Self, Receiver<StorageCommand>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (Self { tx }, rx)
+    }
+
+    /// Queue a message for processing; does not wait for the handler to run.
+    pub async fn process(&self, msg: Message, span: Span) -> anyhow::Result<()> {
+        self.tx
+            .send(StorageCommand::Message(Box::new(msg), span, None))
+            .await
+            .map_err(|_| anyhow::anyhow!("storage actor has stopped"))
+    }
+
+    /// Like `process`, but the handler sends through `reply_tx`.
+    pub async fn process_replying(&self, msg: Message, span: Span, reply_tx: Sender<String>) -> anyhow::Result<()> {
+        self.tx
+            .send(StorageCommand::Message(Box::new(msg), span, Some(reply_tx)))
+            .await
+            .map_err(|_| anyhow::anyhow!("storage actor has stopped"))
+    }
+
+    pub async fn gossip(&self, online: bool) -> anyhow::Result<Vec<BroadcastCommand>> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(StorageCommand::Gossip { online, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("storage actor has stopped"))?;
+        Ok(rx.await?)
+    }
+
+    pub async fn call<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Storage) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, rx) = oneshot::channel();
+        let command = StorageCommand::Call(Box::new(move |storage| {
+            let _ = reply.send(f(storage));
+        }));
+        self.tx
+            .send(command)
+            .await
+            .map_err(|_| anyhow::anyhow!("storage actor has stopped"))?;
+        Ok(rx.await?)
+    }
+
+    pub async fn snapshot_json(&self) -> anyhow::Result<String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(StorageCommand::Snapshot { reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("storage actor has stopped"))?;
+        rx.await?
+    }
+}
+
+/// Own `storage` and apply commands one at a time, so no other task ever
+/// needs a lock on it. Runs until every `StorageHandle` is dropped, then
+/// flushes state to the backend.
+pub async fn run_storage(
+    mut storage: Storage,
+    mut rx: Receiver<StorageCommand>,
+    tx: Sender<String>,
+) -> anyhow::Result<()> {
+    // Messages carry their own span; everything else runs in one naming the
+    // node, once `init` has set it.
+    let mut node_span = Span::none();
+    while let Some(command) = rx.recv().await {
+        if node_span.is_none()
+            && let Some(id) = &storage._node_id
+        {
+            node_span = tracing::info_span!("storage", node = %id);
+        }
+        match command {
+            StorageCommand::Message(msg, span, reply_tx) => {
+                let reply_tx = reply_tx.unwrap_or_else(|| tx.clone());
+                let result = process_message(*msg, &mut storage, reply_tx)
+                    .instrument(span.clone())
+                    .await;
+                if let Err(e) = result {
+                    tracing::warn!(parent: &span, error = %e, "failed to process message");
+                }
+            }
+            StorageCommand::Gossip { online, reply } => {
+                let _ = reply.send(node_span.in_scope(|| storage.gossip_batch(online)));
+            }
+            StorageCommand::Snapshot { reply } => {
+                let _ = reply.send(node_span.in_scope(|| storage.snapshot_json()));
+            }
+            StorageCommand::Call(f) => node_span.in_scope(|| f(&mut storage)),
+        }
+    }
+    storage.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn run_storage_processes_lines_and_answers_queries() {
+        let (gossip_tx, _gossip_rx) = mpsc::channel(10);
+        let (out_tx, mut out_rx) = mpsc::channel(10);
+        let (handle, rx) = StorageHandle::new(10);
+        let actor = tokio::spawn(run_storage(Storage::new(gossip_tx), rx, out_tx));
+
+        let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+        handle
+            .process(serde_json::from_str(init).unwrap(), Span::none())
+            .await
+            .unwrap();
+        let reply: serde_json::Value = serde_json::from_str(&out_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["body"]["type"], "init_ok");
+
+        let snapshot: serde_json::Value =
+            serde_json::from_str(&handle.snapshot_json().await.unwrap()).unwrap();
+        assert_eq!(snapshot["node_id"], "n1");
+
+        drop(handle);
+        actor.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn work_outside_messages_runs_in_a_node_span_after_init() {
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new("info"))
+            .with_writer(std::io::sink)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let (gossip_tx, _gossip_rx) = mpsc::channel(10);
+        let (out_tx, _out_rx) = mpsc::channel(10);
+        let (handle, rx) = StorageHandle::new(10);
+        let actor = tokio::spawn(run_storage(Storage::new(gossip_tx), rx, out_tx));
+        let span_name = || Span::current().metadata().map(|meta| meta.name());
+
+        assert_eq!(handle.call(move |_| span_name()).await.unwrap(), None);
+        handle.call(|storage| storage._node_id = Some("n1".into())).await.unwrap();
+        assert_eq!(handle.call(move |_| span_name()).await.unwrap(), Some("storage"));
+
+        drop(handle);
+        actor.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{Storage, cas::PendingRequest};
+
+/// Maelstrom's `crash` error code, sent when a change could not be logged.
+/// The append may still have reached the file, so the outcome is indefinite.
+pub const NOT_LOGGED: u64 = 13;
+
+/// A single state mutation, appended to the log before it is applied and
+/// acknowledged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalEntry {
+    Value { key: u64, src: String, value: u64 },
+    Counter { node: String, value: u64 },
+    Register { key: String, value: u64 },
+    CasPending { key: u64, request: PendingRequest },
+    CasResolved { key: u64 },
+    /// Snowflake IDs may have been issued with timestamps up to `until_ms`.
+    IdReservation { until_ms: u64 },
+}
+
+/// The durable subset of `Storage`, written periodically so the log can be truncated.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DurableState {
+    pub values: BTreeMap<u64, (String, u64)>,
+    pub counter: HashMap<String, u64>,
+    pub registers: HashMap<String, u64>,
+    pub pending_cas: HashMap<u64, PendingRequest>,
+    #[serde(default)]
+    pub id_reserved_until: u64,
+}
+
+pub trait StorageBackend: Send + Sync {
+    /// Append a mutation to the log.
+    fn append(&mut self, entry: &WalEntry) -> anyhow::Result<()>;
+
+    /// Replace the log with a snapshot of the full durable state.
+    fn snapshot(&mut self, state: &DurableState) -> anyhow::Result<()>;
+
+    /// Load the last snapshot and every entry appended after it.
+    fn load(&mut self) -> anyhow::Result<(Option<DurableState>, Vec<WalEntry>)>;
+
+    /// Whether enough entries have been appended since the last snapshot to take another.
+    fn should_snapshot(&self) -> bool;
+}
+
+/// The default backend when persistence is off: state lives only in
+/// `Storage`, so entries and snapshots are discarded.
+#[derive(Default)]
+pub struct MemoryBackend;
+
+impl StorageBackend for MemoryBackend {
+    fn append(&mut self, _entry: &WalEntry) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&mut self, _state: &DurableState) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> anyhow::Result<(Option<DurableState>, Vec<WalEntry>)> {
+        Ok((None, Vec::new()))
+    }
+
+    fn should_snapshot(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps every entry and the last snapshot in memory, for tests that
+/// inspect what would have been persisted.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct RecordingBackend {
+    snapshot: Option<DurableState>,
+    log: Vec<WalEntry>,
+}
+
+#[cfg(test)]
+impl StorageBackend for RecordingBackend {
+    fn append(&mut self, entry: &WalEntry) -> anyhow::Result<()> {
+        self.log.push(entry.clone());
+        Ok(())
+    }
+
+    fn snapshot(&mut self, state: &DurableState) -> anyhow::Result<()> {
+        self.snapshot = Some(state.clone());
+        self.log.clear();
+        Ok(())
+    }
+
+    fn load(&mut self) -> anyhow::Result<(Option<DurableState>, Vec<WalEntry>)> {
+        Ok((self.snapshot.clone(), self.log.clone()))
+    }
+
+    fn should_snapshot(&self) -> bool {
+        false
+    }
+}
+
+impl Storage {
+    /// Swap in a new backend and rebuild state from whatever it has recorded.
+    pub fn attach_backend(&mut self, mut backend: Box<dyn StorageBackend>) -> anyhow::Result<()> {
+        let (snapshot, entries) = backend.load()?;
+        if let Some(state) = snapshot {
+            self.values = state.values;
+            self.counter = state.counter;
+            self.registers = state.registers;
+            self.pending_cas = state.pending_cas;
+            self.resume_ids_after(state.id_reserved_until);
+        }
+        for entry in entries {
+            self.apply(entry);
+        }
+        self.backend = backend;
+        Ok(())
+    }
+
+    pub fn durable_state(&self) -> DurableState {
+        DurableState {
+            values: self.values.clone(),
+            counter: self.counter.clone(),
+            registers: self.registers.clone(),
+            pending_cas: self.pending_cas.clone(),
+            id_reserved_until: self.id_reserved_until,
+        }
+    }
+
+    /// Write a final snapshot so a restart does not need to replay the log.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        let state = self.durable_state();
+        self.backend.snapshot(&state)
+    }
+
+    /// Append `entry`, failing if it did not reach the log; callers apply
+    /// the change only once this succeeds. A due snapshot is taken first,
+    /// while every logged entry is also applied, and its failure is only
+    /// logged since the log still holds everything.
+    pub(crate) fn try_persist(&mut self, entry: WalEntry) -> anyhow::Result<()> {
+        if self.backend.should_snapshot() {
+            let state = self.durable_state();
+            if let Err(e) = self.backend.snapshot(&state) {
+                tracing::error!(error = %e, "failed to write snapshot");
+            }
+        }
+        self.backend
+            .append(&entry)
+            .with_context(|| format!("Failed to append {:?}", entry))
+    }
+
+    fn apply(&mut self, entry: WalEntry) {
+        match entry {
+            WalEntry::Value { key, src, value } => {
+                self.values.insert(key, (src, value));
+            }
+            WalEntry::Counter { node, value } => {
+                let current = self.counter.entry(node).or_insert(value);
+                *current = (*current).max(value);
+            }
+            WalEntry::Register { key, value } => {
+                self.registers.insert(key, value);
+            }
+            WalEntry::CasPending { key, request } => {
+                self.pending_cas.insert(key, request);
+            }
+            WalEntry::CasResolved { key } => {
+                self.pending_cas.remove(&key);
+            }
+            WalEntry::IdReservation { until_ms } => self.resume_ids_after(until_ms),
+        }
+    }
+
+    pub(super) fn resume_ids_after(&mut self, until_ms: u64) {
+        if until_ms > self.id_reserved_until {
+            self.id_reserved_until = until_ms;
+            self.snowflake.resume_after(until_ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+
+    #[tokio::test]
+    async fn attach_backend_replays_snapshot_then_log() {
+        let mut backend = RecordingBackend::default();
+        let mut state = DurableState::default();
+        state.registers.insert("a".into(), 1);
+        backend.snapshot(&state).unwrap();
+        backend
+            .append(&WalEntry::Register {
+                key: "a".into(),
+                value: 2,
+            })
+            .unwrap();
+        backend
+            .append(&WalEntry::Counter {
+                node: "node-B".into(),
+                value: 7,
+            })
+            .unwrap();
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.attach_backend(Box::new(backend)).unwrap();
+
+        assert_eq!(store.kv_read("a"), Ok(2));
+        assert_eq!(store.g_counter_value(), 7);
+    }
+
+    #[tokio::test]
+    async fn mutations_are_appended_to_backend() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("node-A").await;
+        store.attach_backend(Box::<RecordingBackend>::default()).unwrap();
+
+        store.kv_write("a".into(), 1).unwrap();
+        store.update_values("client".into(), 42).unwrap();
+
+        let (_, entries) = store.backend.load().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[1], WalEntry::Value { value: 42, .. }));
+    }
+
+    #[tokio::test]
+    async fn ids_stay_unique_across_restart_with_regressed_clock() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        // The log is attached by hand below; `data_dir` is only read by `init`.
+        let config = crate::config::Config {
+            id_reserve_ms: 500,
+            data_dir: Some("unused".into()),
+            ..Default::default()
+        };
+        let mut store = Storage::new_with_config(tx.clone(), config.clone()).unwrap();
+        store.set_id("n1").await;
+        store.attach_backend(Box::<RecordingBackend>::default()).unwrap();
+        let before: Vec<u64> = (0..100).map(|_| store.next_id()).collect();
+        let reserved = store.durable_state().id_reserved_until;
+        let (snapshot, entries) = store.backend.load().unwrap();
+        assert!(entries.iter().any(|e| matches!(e, WalEntry::IdReservation { .. })));
+
+        // The reservation runs ahead of the clock, so a fresh process is in
+        // the same spot as one whose clock regressed: replaying it puts the
+        // generator past every ID the old one could have issued.
+        let mut backend = RecordingBackend::default();
+        if let Some(state) = snapshot {
+            backend.snapshot(&state).unwrap();
+        }
+        for entry in &entries {
+            backend.append(entry).unwrap();
+        }
+        let mut restarted = Storage::new_with_config(tx, config).unwrap();
+        restarted.set_id("n1").await;
+        restarted.attach_backend(Box::new(backend)).unwrap();
+
+        let after = restarted.next_id();
+        assert!(before.iter().all(|id| *id < after));
+        assert!(restarted.snowflake.decode(after).timestamp_ms > reserved);
+    }
+
+    /// Refuses every append, like a full or failed disk.
+    struct FailingBackend;
+
+    impl StorageBackend for FailingBackend {
+        fn append(&mut self, _entry: &WalEntry) -> anyhow::Result<()> {
+            anyhow::bail!("disk full")
+        }
+
+        fn snapshot(&mut self, _state: &DurableState) -> anyhow::Result<()> {
+            anyhow::bail!("disk full")
+        }
+
+        fn load(&mut self) -> anyhow::Result<(Option<DurableState>, Vec<WalEntry>)> {
+            Ok((None, Vec::new()))
+        }
+
+        fn should_snapshot(&self) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_fails_when_the_reservation_cannot_be_logged() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.config.id_reserve_ms = 500;
+        store.set_id("n1").await;
+        store.install_id_generator();
+        store.attach_backend(Box::new(FailingBackend)).unwrap();
+
+        assert!(store.generate_id().is_err());
+        assert_eq!(store.durable_state().id_reserved_until, 0);
+    }
+
+    #[tokio::test]
+    async fn unlogged_changes_are_refused_and_not_applied() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("n1").await;
+        store.attach_backend(Box::new(FailingBackend)).unwrap();
+
+        assert_eq!(store.kv_write("a".into(), 1).unwrap_err().code(), NOT_LOGGED);
+        assert!(store.kv_cas("a".into(), 0, 1, true).is_err());
+        assert!(store.update_values("c1".into(), 5).is_err());
+        let counter = HashMap::from([("n1".to_string(), 3)]);
+        assert!(store.update_counter(counter, None).await.is_err());
+
+        assert_eq!(store.durable_state(), DurableState::default());
+    }
+
+    #[tokio::test]
+    async fn unlogged_write_is_answered_with_an_error() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.attach_backend(Box::new(FailingBackend)).unwrap();
+
+        crate::handlers::write::handle_write("c1".into(), "n1".into(), 4, "a".into(), 1, &mut store, out_tx)
+            .await
+            .unwrap();
+
+        let reply: serde_json::Value = serde_json::from_str(&out_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["body"]["type"], "error");
+        assert_eq!(reply["body"]["code"], NOT_LOGGED);
+        assert_eq!(reply["body"]["in_reply_to"], 4);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+/// When a gossiped value was last sent to a peer and when it is next due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    pub attempts: u32,
+    pub next_at: u64,
+}
+
+/// Per-(peer, key) retransmission schedule. A key with no entry has never
+/// been sent and is due immediately; each send doubles the wait up to a cap,
+/// and an ack forgets the entry.
+#[derive(Debug, Default)]
+pub struct RetrySchedule {
+    retries: HashMap<String, HashMap<u64, Retry>>,
+    rng: u64,
+}
+
+impl RetrySchedule {
+    pub fn get(&self, node: &str, key: u64) -> Option<Retry> {
+        self.retries.get(node)?.get(&key).copied()
+    }
+
+    pub fn is_due(&self, node: &str, key: u64, now: u64) -> bool {
+        self.get(node, key).is_none_or(|retry| retry.next_at <= now)
+    }
+
+    /// Note a send and schedule the next one `base_ms * 2^attempts` later,
+    /// capped at `max_ms` and shortened by up to `jitter` of itself so peers
+    /// recovering together are not hit in lockstep.
+    pub fn record_send(&mut self, node: &str, key: u64, now: u64, base_ms: u64, max_ms: u64, jitter: f64) {
+        if self.rng == 0 {
+            self.rng = now | 1;
+        }
+        let unit = (xorshift(&mut self.rng) >> 11) as f64 / (1u64 << 53) as f64;
+        let retries = self.retries.entry(node.to_string()).or_default();
+        let retry = retries.entry(key).or_insert(Retry {
+            attempts: 0,
+            next_at: now,
+        });
+        let delay = backoff_delay(retry.attempts, base_ms, max_ms);
+        let delay = delay - (delay as f64 * jitter * unit) as u64;
+        retry.attempts = retry.attempts.saturating_add(1);
+        retry.next_at = now + delay;
+    }
+
+    pub fn reset(&mut self, node: &str, key: u64) {
+        if let Some(retries) = self.retries.get_mut(node) {
+            retries.remove(&key);
+        }
+    }
+
+    /// Forget every schedule for `node`, e.g. when it comes back and should
+    /// be caught up straight away.
+    pub fn reset_node(&mut self, node: &str) {
+        self.retries.remove(node);
+    }
+}
+
+/// Un-jittered wait after `attempts` previous sends.
+pub fn backoff_delay(attempts: u32, base_ms: u64, max_ms: u64) -> u64 {
+    base_ms
+        .saturating_mul(1u64.checked_shl(attempts).unwrap_or(u64::MAX))
+        .min(max_ms)
+}
+
+pub(crate) fn xorshift(state: &mut u64) -> u64 {
+    let mut x = (*state).max(1);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_until_capped() {
+        let delays: Vec<u64> = (0..6).map(|n| backoff_delay(n, 100, 1_000)).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(backoff_delay(200, 100, 1_000), 1_000);
+    }
+
+    #[test]
+    fn sends_are_spaced_out_and_reset_on_ack() {
+        let mut schedule = RetrySchedule::default();
+        assert!(schedule.is_due("n2", 1, 0));
+
+        let mut now = 0;
+        for attempt in 0..5 {
+            schedule.record_send("n2", 1, now, 100, 1_000, 0.5);
+            let retry = schedule.get("n2", 1).unwrap();
+            let full = backoff_delay(attempt, 100, 1_000);
+            assert!(retry.next_at - now <= full && retry.next_at - now >= full / 2);
+            assert!(!schedule.is_due("n2", 1, now + full / 2 - 1));
+            now = retry.next_at;
+            assert!(schedule.is_due("n2", 1, now));
+        }
+
+        schedule.reset("n2", 1);
+        assert_eq!(schedule.get("n2", 1), None);
+    }
+}
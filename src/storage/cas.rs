@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PendingRequest {
     pub dest: String,
     pub from: u64,
@@ -10,27 +12,31 @@ pub struct PendingRequest {
 
 use crate::broadcast::actor::BroadcastCommand;
 
+use super::backend::WalEntry;
+
 use super::Storage;
 
 impl Storage {
-    pub async fn process_add(&mut self, delta: u64, dest: String, msg_id: u64) {
+    pub async fn process_add(&mut self, delta: u64, dest: String, msg_id: u64) -> anyhow::Result<()> {
         let key = self.next_id();
         let from = self.g_counter_value();
         let to = from + delta;
         let mut update = HashMap::new();
         update.insert(self._node_id.as_ref().unwrap().clone(), to);
-        self.update_counter(update);
-
-        self.pending_cas.insert(
+        self.update_counter(update, None).await?;
+
+        let request = PendingRequest {
+            dest,
+            from,
+            to,
+            msg_id,
+        };
+        self.try_persist(WalEntry::CasPending {
             key,
-            PendingRequest {
-                dest,
-                from,
-                to,
-                msg_id,
-            },
-        );
-        let _ = self.tx
+            request: request.clone(),
+        })?;
+        self.pending_cas.insert(key, request);
+        self.tx
             .send(BroadcastCommand::Cas {
                 dest: "seq-kv".to_string(),
                 msg_id: key,
@@ -38,18 +44,30 @@ impl Storage {
                 to,
                 create_if_not_exists: true,
             })
-            .await;
+            .await?;
+        Ok(())
     }
 
-    pub async fn retry_for_cas(&mut self, msg_id: u64) {
-        if let Some(mut cas_request) = self.pending_cas.remove(&msg_id) {
+    /// Re-send a rejected CAS. If later adds have raised the local total past
+    /// the request's target, the retry carries the local total instead: it
+    /// includes this request's delta, so nothing is lost by subsuming it.
+    pub async fn retry_for_cas(&mut self, msg_id: u64) -> anyhow::Result<()> {
+        if let Some(mut cas_request) = self.pending_cas.get(&msg_id).cloned() {
             let key = self.next_id();
-            if self.g_counter_value() > cas_request.to {
-                return;
+            let local = self.g_counter_value();
+            if local > cas_request.to {
+                tracing::debug!(msg_id, to = cas_request.to, local, "retrying cas with the newer local total");
+                cas_request.to = local;
             }
-            cas_request.from = self.g_counter_value();
+            cas_request.from = local;
+            self.try_persist(WalEntry::CasPending {
+                key,
+                request: cas_request.clone(),
+            })?;
             self.pending_cas.insert(key, cas_request.clone());
-            let _ = self.tx
+            self.try_persist(WalEntry::CasResolved { key: msg_id })?;
+            self.pending_cas.remove(&msg_id);
+            self.tx
                 .send(BroadcastCommand::Cas {
                     dest: "seq-kv".to_string(),
                     msg_id: key,
@@ -57,20 +75,30 @@ impl Storage {
                     to: cas_request.to,
                     create_if_not_exists: true,
                 })
-                .await;
+                .await?;
         }
+        Ok(())
     }
 
-    pub fn remove_request_from_pending_cas(&mut self, msg_id: u64) {
-        eprintln!("{:?} {:?}", self.pending_cas, msg_id);
+    pub async fn remove_request_from_pending_cas(&mut self, msg_id: u64) -> anyhow::Result<()> {
+        tracing::debug!(
+            msg_id,
+            key = %self.key_provenance(msg_id),
+            pending = self.pending_cas.len(),
+            "cas acknowledged"
+        );
 
-        if let Some(cas_request) = self.pending_cas.remove(&msg_id) {
-            let mut update = HashMap::new();
-            update.insert(self._node_id.as_ref().unwrap().clone(), cas_request.to);
-            self.update_counter(update);
-        } else {
-            panic!("There was no key found")
-        }
+        // A retried CAS can be acked twice, and replayed or misrouted acks
+        // may name ids we never issued; neither should take the node down.
+        let Some(cas_request) = self.pending_cas.get(&msg_id).cloned() else {
+            tracing::debug!(msg_id, "cas_ok for unknown request; ignoring");
+            return Ok(());
+        };
+        self.try_persist(WalEntry::CasResolved { key: msg_id })?;
+        self.pending_cas.remove(&msg_id);
+        let mut update = HashMap::new();
+        update.insert(self._node_id.as_ref().unwrap().clone(), cas_request.to);
+        self.update_counter(update, None).await
     }
 }
 
@@ -82,11 +110,11 @@ mod tests {
 
     #[tokio::test]
     async fn process_add_sends_cas_and_updates_pending() {
-        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
         let mut store = Storage::new(tx);
         store.set_id("node-A").await;
 
-        store.process_add(10, "node-B".to_string(), 1).await;
+        store.process_add(10, "node-B".to_string(), 1).await.unwrap();
 
         assert_eq!(store.pending_cas.len(), 1);
 
@@ -100,13 +128,30 @@ mod tests {
 
         store.pending_cas.insert(1, PendingRequest { dest: "node-B".to_string(), from: 0, to: 10, msg_id: 1 });
 
-        store.retry_for_cas(1).await;
+        store.retry_for_cas(1).await.unwrap();
 
         assert_eq!(store.pending_cas.len(), 1);
         let broadcast = rx.recv().await.unwrap();
         assert!(matches!(broadcast, BroadcastCommand::Cas { .. }));
     }
 
+    #[tokio::test]
+    async fn retry_for_cas_is_not_dropped_when_the_local_total_moved_on() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("node-A").await;
+
+        store.counter.insert("node-A".to_string(), 25);
+        store.pending_cas.insert(1, PendingRequest { dest: "node-B".to_string(), from: 0, to: 10, msg_id: 1 });
+
+        store.retry_for_cas(1).await.unwrap();
+
+        assert_eq!(store.pending_cas.len(), 1);
+        assert!(store.pending_cas.values().all(|request| request.to == 25));
+        let broadcast = rx.recv().await.unwrap();
+        assert!(matches!(broadcast, BroadcastCommand::Cas { to: 25, .. }));
+    }
+
     #[tokio::test]
     async fn remove_request_from_pending_cas_removes_and_updates_counter() {
         let (tx, _rx) = tokio::sync::mpsc::channel(10);
@@ -115,9 +160,20 @@ mod tests {
 
         store.pending_cas.insert(1, PendingRequest { dest: "node-B".to_string(), from: 0, to: 10, msg_id: 1 });
 
-        store.remove_request_from_pending_cas(1);
+        store.remove_request_from_pending_cas(1).await.unwrap();
 
         assert_eq!(store.pending_cas.len(), 0);
         assert_eq!(store.g_counter_value(), 10);
     }
+
+    #[tokio::test]
+    async fn remove_request_from_pending_cas_ignores_unknown_ids() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("node-A").await;
+
+        store.remove_request_from_pending_cas(42).await.unwrap();
+
+        assert_eq!(store.g_counter_value(), 0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// Per-node counters of broadcasts delivered from each origin. Missing
+/// entries count as zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock(pub BTreeMap<String, u64>);
+
+impl VectorClock {
+    /// Zero clock with an entry for every node.
+    pub fn over(node_ids: &[String]) -> Self {
+        Self(node_ids.iter().map(|node| (node.clone(), 0)).collect())
+    }
+
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    pub fn increment(&mut self, node: &str) -> u64 {
+        let entry = self.0.entry(node.to_string()).or_insert(0);
+        *entry += 1;
+        *entry
+    }
+
+    /// True if every entry is at most the matching entry in `other`.
+    pub fn le(&self, other: &VectorClock) -> bool {
+        self.0.iter().all(|(node, count)| *count <= other.get(node))
+    }
+}
+
+/// Causal metadata carried with a broadcast value: the node that first
+/// accepted it, and that node's delivered clock at the time, with its own
+/// entry counting this value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalTag {
+    pub origin: String,
+    pub clock: VectorClock,
+}
+
+impl CausalTag {
+    fn seq(&self) -> u64 {
+        self.clock.get(&self.origin)
+    }
+}
+
+/// Causal-broadcast bookkeeping, used when `Config::causal_broadcast` is set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CausalState {
+    /// How many broadcasts from each origin have been delivered to `values`.
+    pub delivered: VectorClock,
+    /// Arrivals whose causal dependencies have not been delivered yet.
+    pub buffered: Vec<(String, u64, CausalTag)>,
+    /// Tag of each delivered value, keyed like `Storage::values`, so it can
+    /// be forwarded to peers unchanged.
+    pub tags: BTreeMap<u64, CausalTag>,
+}
+
+impl CausalState {
+    /// Everything from `tag.origin` before this value, and everything the
+    /// origin had delivered when it sent it, is already delivered here.
+    fn deliverable(&self, tag: &CausalTag) -> bool {
+        tag.seq() == self.delivered.get(&tag.origin) + 1
+            && tag
+                .clock
+                .0
+                .iter()
+                .filter(|(node, _)| **node != tag.origin)
+                .all(|(node, count)| *count <= self.delivered.get(node))
+    }
+
+    fn seen(&self, tag: &CausalTag) -> bool {
+        tag.seq() <= self.delivered.get(&tag.origin)
+            || self
+                .buffered
+                .iter()
+                .any(|(_, _, queued)| queued.origin == tag.origin && queued.seq() == tag.seq())
+    }
+}
+
+impl Storage {
+    /// Accept a broadcast value in causal mode. Values from clients get a
+    /// fresh tag and are delivered at once; values from peers are delivered
+    /// once their dependencies are, possibly releasing buffered ones.
+    pub fn broadcast_causal(&mut self, src: String, value: u64, tag: Option<CausalTag>) -> anyhow::Result<()> {
+        let tag = match tag {
+            Some(tag) => tag,
+            None => {
+                let me = self._node_id.clone().expect("Node Id not set");
+                let mut clock = self.causal.delivered.clone();
+                clock.increment(&me);
+                CausalTag { origin: me, clock }
+            }
+        };
+        if self.causal.seen(&tag) {
+            return Ok(());
+        }
+        if !self.causal.deliverable(&tag) {
+            tracing::debug!(origin = %tag.origin, seq = tag.seq(), "buffering causal broadcast");
+            self.causal.buffered.push((src, value, tag));
+            return Ok(());
+        }
+        self.deliver_causal(&src, value, &tag)?;
+        while let Some(i) = self
+            .causal
+            .buffered
+            .iter()
+            .position(|(_, _, tag)| self.causal.deliverable(tag))
+        {
+            // Stays buffered if it cannot be logged, for the next delivery to retry.
+            let (src, value, tag) = self.causal.buffered[i].clone();
+            self.deliver_causal(&src, value, &tag)?;
+            self.causal.buffered.remove(i);
+        }
+        Ok(())
+    }
+
+    fn deliver_causal(&mut self, src: &str, value: u64, tag: &CausalTag) -> anyhow::Result<()> {
+        let key = self.insert_value(src.to_string(), value)?;
+        self.causal.delivered.0.insert(tag.origin.clone(), tag.seq());
+        self.causal.tags.insert(key, tag.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(origin: &str, clock: &[(&str, u64)]) -> CausalTag {
+        CausalTag {
+            origin: origin.into(),
+            clock: VectorClock(clock.iter().map(|(n, c)| (n.to_string(), *c)).collect()),
+        }
+    }
+
+    async fn causal_store() -> Storage {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.config.causal_broadcast = true;
+        store.set_id("n1").await;
+        store.update_typology(vec!["n2".into(), "n3".into()]);
+        store
+    }
+
+    #[tokio::test]
+    async fn client_values_are_tagged_in_sequence() {
+        let mut store = causal_store().await;
+
+        store.broadcast_causal("c1".into(), 10, None).unwrap();
+        store.broadcast_causal("c1".into(), 11, None).unwrap();
+
+        assert_eq!(store.values(), vec![10, 11]);
+        assert_eq!(store.causal.delivered.get("n1"), 2);
+        let tags: Vec<_> = store.causal.tags.values().map(CausalTag::seq).collect();
+        assert_eq!(tags, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn out_of_order_arrivals_wait_for_dependencies() {
+        let mut store = causal_store().await;
+
+        // n3 saw n2's first value before sending its own.
+        store.broadcast_causal("n3".into(), 31, Some(tag("n3", &[("n2", 1), ("n3", 1)]))).unwrap();
+        store.broadcast_causal("n2".into(), 22, Some(tag("n2", &[("n2", 2)]))).unwrap();
+        assert!(store.values().is_empty());
+        assert_eq!(store.causal.buffered.len(), 2);
+
+        store.broadcast_causal("n2".into(), 21, Some(tag("n2", &[("n2", 1)]))).unwrap();
+        assert_eq!(store.values(), vec![21, 31, 22]);
+        assert!(store.causal.buffered.is_empty());
+
+        // Redelivery via another peer is ignored.
+        store.broadcast_causal("n3".into(), 21, Some(tag("n2", &[("n2", 1)]))).unwrap();
+        assert_eq!(store.values().len(), 3);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+/// Phi accrual failure detector (Hayashibara et al.), fed with ack arrival times.
+///
+/// `phi` grows the longer a peer stays silent relative to how regularly it has
+/// acked so far, so the threshold adapts to each peer's observed latency.
+#[derive(Debug, Clone)]
+pub struct PhiAccrual {
+    intervals: VecDeque<u64>,
+    last: Option<u64>,
+    window: usize,
+    min_std_dev_ms: f64,
+}
+
+/// Coarse view of `phi`, used to pick how often to retry a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suspicion {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+impl PhiAccrual {
+    pub fn new(window: usize, min_std_dev_ms: f64) -> Self {
+        Self {
+            intervals: VecDeque::with_capacity(window),
+            last: None,
+            window,
+            min_std_dev_ms,
+        }
+    }
+
+    /// Record an ack arriving at `now`.
+    pub fn heartbeat(&mut self, now: u64) {
+        if let Some(last) = self.last {
+            if self.intervals.len() == self.window {
+                self.intervals.pop_front();
+            }
+            self.intervals.push_back(now.saturating_sub(last));
+        }
+        self.last = Some(now);
+    }
+
+    /// Restart the silence clock without recording an interval, for when we
+    /// start expecting acks again after a stretch with nothing to send.
+    pub fn resume(&mut self, now: u64) {
+        self.last = Some(now);
+    }
+
+    /// Whether at least one ack interval has been observed.
+    pub fn has_history(&self) -> bool {
+        !self.intervals.is_empty()
+    }
+
+    /// Suspicion that the peer has failed, or `None` before any history exists.
+    pub fn phi(&self, now: u64) -> Option<f64> {
+        let last = self.last?;
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let n = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<u64>() as f64 / n;
+        let variance = self
+            .intervals
+            .iter()
+            .map(|i| (*i as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let std_dev = variance.sqrt().max(self.min_std_dev_ms);
+        let elapsed = now.saturating_sub(last) as f64;
+
+        // Logistic approximation of the normal CDF, as used by Akka and Cassandra.
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        let phi = if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        };
+        Some(phi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phi_rises_with_silence() {
+        let mut detector = PhiAccrual::new(100, 10.0);
+        for t in (0..=1_000).step_by(100) {
+            detector.heartbeat(t);
+        }
+
+        let on_time = detector.phi(1_100).unwrap();
+        let late = detector.phi(1_300).unwrap();
+        let very_late = detector.phi(2_000).unwrap();
+
+        assert!(on_time < 1.0);
+        assert!(late > on_time);
+        assert!(very_late > 8.0);
+    }
+
+    #[test]
+    fn phi_is_unknown_without_history() {
+        let mut detector = PhiAccrual::new(100, 10.0);
+        assert_eq!(detector.phi(10), None);
+        detector.heartbeat(0);
+        assert_eq!(detector.phi(10), None);
+    }
+
+    #[test]
+    fn resume_resets_silence_without_skewing_intervals() {
+        let mut detector = PhiAccrual::new(100, 10.0);
+        for t in (0..=1_000).step_by(100) {
+            detector.heartbeat(t);
+        }
+        detector.resume(60_000);
+
+        assert!(detector.phi(60_050).unwrap() < 1.0);
+    }
+}
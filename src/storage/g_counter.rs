@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use crate::{broadcast::actor::BroadcastCommand, message::BroadcastMessage};
 
-use super::Storage;
+use super::{Storage, backend::WalEntry};
 
 impl Storage {
     pub fn g_counter_value(&self) -> u64 {
@@ -12,24 +12,74 @@ impl Storage {
     pub fn g_counter_node_value(&mut self) -> u64 {
         let node_id = self._node_id.as_ref().expect("Node Id not set");
         let value = self.counter.entry(node_id.to_string()).or_insert(0);
-        value.clone()
+        *value
     }
 
-    pub fn update_counter(&mut self, mut values: HashMap<String, u64>) {
-        for (node, value) in values.iter_mut() {
-            let entry = self.counter.entry(node.clone()).or_insert(value.clone());
-            if entry < value {
-                *entry = value.clone()
+    /// Merge `values` into the counter and, if any entry rose, pass the
+    /// merged map on to every peer except `from`, the node it came from.
+    /// Gossip that teaches us nothing stops here, so peers that list each
+    /// other do not bounce the same map back and forth.
+    pub async fn update_counter(&mut self, values: HashMap<String, u64>, from: Option<&str>) -> anyhow::Result<()> {
+        let mut changed = false;
+        for (node, value) in values {
+            if self.counter.get(&node).is_some_and(|current| *current >= value) {
+                continue;
             }
+            self.try_persist(WalEntry::Counter {
+                node: node.clone(),
+                value,
+            })?;
+            self.counter.insert(node, value);
+            changed = true;
         }
-        let nodes: Vec<String> = self.topology.iter().cloned().collect();
+        if !changed {
+            return Ok(());
+        }
+        let nodes: Vec<String> = self
+            .topology
+            .iter()
+            .filter(|node| Some(node.as_str()) != from)
+            .cloned()
+            .collect();
+        for command in self.counter_sends(nodes) {
+            self.tx.send(command).await?;
+        }
+        Ok(())
+    }
+
+    /// The counter map for each of `nodes`, each under a fresh msg_id that
+    /// the peer must ack before it stops being resent.
+    pub fn counter_sends(&mut self, nodes: Vec<String>) -> Vec<BroadcastCommand> {
+        let mut commands = Vec::with_capacity(nodes.len());
         for node in nodes {
-            _ = self.tx.send(BroadcastCommand::Broadcast {
+            let msg_id = self.next_id();
+            self.counter_unacked.insert(node.clone(), msg_id);
+            commands.push(BroadcastCommand::Broadcast {
                 dest: node,
-                msg_id: self.next_id(),
+                msg_id,
                 message: BroadcastMessage::Hashmap(self.counter.clone()),
+                causal: None,
+                seq: None,
             });
         }
+        commands
+    }
+
+    /// Resend the counter map to those of `nodes` that never acked the last copy.
+    pub fn counter_resends(&mut self, nodes: &[String]) -> Vec<BroadcastCommand> {
+        let owed = nodes
+            .iter()
+            .filter(|node| self.counter_unacked.contains_key(*node))
+            .cloned()
+            .collect();
+        self.counter_sends(owed)
+    }
+
+    /// Stop resending the counter to `node` if `msg_id` acks its latest copy.
+    pub fn counter_acked(&mut self, node: &str, msg_id: u64) {
+        if self.counter_unacked.get(node) == Some(&msg_id) {
+            self.counter_unacked.remove(node);
+        }
     }
 }
 
@@ -72,9 +122,59 @@ mod tests {
         values.insert("node-A".to_string(), 20);
         values.insert("node-B".to_string(), 30);
 
-        store.update_counter(values);
+        store.update_counter(values, None).await.unwrap();
 
         assert_eq!(store.counter.get("node-A"), Some(&20));
         assert_eq!(store.counter.get("node-B"), Some(&30));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(BroadcastCommand::Broadcast { dest, .. }) if dest == "node-B"
+        ));
+    }
+
+    #[tokio::test]
+    async fn update_counter_only_forwards_news_and_not_to_its_sender() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("node-A").await;
+        store.update_typology(vec!["node-B".into(), "node-C".into()]);
+
+        let gossip = HashMap::from([("node-B".to_string(), 5)]);
+        store.update_counter(gossip.clone(), Some("node-B")).await.unwrap();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(BroadcastCommand::Broadcast { dest, .. }) if dest == "node-C"
+        ));
+        assert!(rx.try_recv().is_err());
+
+        store.update_counter(gossip, Some("node-C")).await.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn counter_is_resent_until_the_latest_copy_is_acked() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("node-A").await;
+        store.update_typology(vec!["node-B".into()]);
+
+        store.update_counter(HashMap::from([("node-A".to_string(), 3)]), None).await.unwrap();
+        let Ok(BroadcastCommand::Broadcast { msg_id: first, .. }) = rx.try_recv() else {
+            panic!("expected the counter to be sent");
+        };
+
+        let peers = vec!["node-B".to_string()];
+        let resent = store.counter_resends(&peers);
+        let [BroadcastCommand::Broadcast { msg_id: second, .. }] = resent.as_slice() else {
+            panic!("expected one resend");
+        };
+        // An ack for an older copy does not cover the latest one.
+        store.counter_acked("node-B", first);
+        assert_eq!(store.counter_resends(&peers).len(), 1);
+
+        let latest = store.counter_unacked["node-B"];
+        assert_ne!(latest, *second);
+        store.counter_acked("node-B", latest);
+        assert!(store.counter_resends(&peers).is_empty());
     }
 }
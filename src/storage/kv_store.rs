@@ -0,0 +1,139 @@
+use super::{
+    Storage,
+    backend::{NOT_LOGGED, WalEntry},
+};
+
+/// Maelstrom error codes returned by the keyed register store.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KvError {
+    KeyDoesNotExist(String),
+    PreconditionFailed { key: String, expected: u64, found: u64 },
+    /// The write-ahead log refused the change, so it was not applied.
+    NotLogged { key: String, error: String },
+}
+
+impl KvError {
+    pub fn code(&self) -> u64 {
+        match self {
+            KvError::KeyDoesNotExist(_) => 20,
+            KvError::PreconditionFailed { .. } => 22,
+            KvError::NotLogged { .. } => NOT_LOGGED,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            KvError::KeyDoesNotExist(key) => format!("key {} does not exist", key),
+            KvError::PreconditionFailed {
+                key,
+                expected,
+                found,
+            } => format!("key {} expected {} but found {}", key, expected, found),
+            KvError::NotLogged { key, error } => format!("write to key {} was not logged: {}", key, error),
+        }
+    }
+}
+
+impl Storage {
+    pub fn kv_read(&self, key: &str) -> Result<u64, KvError> {
+        self.registers
+            .get(key)
+            .copied()
+            .ok_or_else(|| KvError::KeyDoesNotExist(key.to_string()))
+    }
+
+    pub fn kv_write(&mut self, key: String, value: u64) -> Result<(), KvError> {
+        self.log_register(&key, value)?;
+        self.registers.insert(key, value);
+        Ok(())
+    }
+
+    pub fn kv_cas(
+        &mut self,
+        key: String,
+        from: u64,
+        to: u64,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError> {
+        match self.registers.get(&key).copied() {
+            Some(current) if current == from => {
+                self.log_register(&key, to)?;
+                self.registers.insert(key, to);
+                Ok(())
+            }
+            Some(current) => Err(KvError::PreconditionFailed {
+                key,
+                expected: from,
+                found: current,
+            }),
+            None if create_if_not_exists => {
+                self.log_register(&key, to)?;
+                self.registers.insert(key, to);
+                Ok(())
+            }
+            None => Err(KvError::KeyDoesNotExist(key)),
+        }
+    }
+
+    fn log_register(&mut self, key: &str, value: u64) -> Result<(), KvError> {
+        let entry = WalEntry::Register {
+            key: key.to_string(),
+            value,
+        };
+        self.try_persist(entry).map_err(|e| KvError::NotLogged {
+            key: key.to_string(),
+            error: format!("{:#}", e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::Storage;
+
+    #[tokio::test]
+    async fn kv_read_missing_key_returns_key_does_not_exist() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let store = Storage::new(tx);
+
+        let err = store.kv_read("1").unwrap_err();
+        assert_eq!(err.code(), 20);
+    }
+
+    #[tokio::test]
+    async fn kv_write_then_read_returns_value() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+
+        store.kv_write("1".into(), 5).unwrap();
+        store.kv_write("1".into(), 7).unwrap();
+
+        assert_eq!(store.kv_read("1"), Ok(7));
+    }
+
+    #[tokio::test]
+    async fn kv_cas_swaps_only_when_from_matches() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.kv_write("1".into(), 5).unwrap();
+
+        let err = store.kv_cas("1".into(), 4, 9, false).unwrap_err();
+        assert_eq!(err.code(), 22);
+        assert_eq!(store.kv_read("1"), Ok(5));
+
+        store.kv_cas("1".into(), 5, 9, false).unwrap();
+        assert_eq!(store.kv_read("1"), Ok(9));
+    }
+
+    #[tokio::test]
+    async fn kv_cas_creates_missing_key_only_when_requested() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+
+        let err = store.kv_cas("2".into(), 0, 1, false).unwrap_err();
+        assert_eq!(err.code(), 20);
+
+        store.kv_cas("2".into(), 0, 1, true).unwrap();
+        assert_eq!(store.kv_read("2"), Ok(1));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Storage, backoff::xorshift, node_state::NodeStatus};
+
+/// A peer's liveness as agreed through SWIM dissemination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberState {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// One piggybacked membership fact. Higher incarnations win; at equal
+/// incarnation Dead beats Suspect beats Alive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberUpdate {
+    pub node: String,
+    pub state: MemberState,
+    pub incarnation: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub state: MemberState,
+    pub incarnation: u64,
+    /// When the member entered its current state.
+    pub since: u64,
+}
+
+/// SWIM bookkeeping: the agreed view of every peer plus the queue of updates
+/// still to be piggybacked on outgoing probes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Membership {
+    pub members: HashMap<String, Member>,
+    pub incarnation: u64,
+    probe_order: Vec<String>,
+    next_probe: usize,
+    rng: u64,
+    broadcasts: VecDeque<(MemberUpdate, usize)>,
+}
+
+/// Most updates piggybacked on a single message.
+const MAX_PIGGYBACK: usize = 8;
+
+impl Storage {
+    /// Start tracking every other node named in `init`.
+    pub fn init_members(&mut self, node_ids: &[String]) {
+        let now = (self.clock)();
+        let me = self._node_id.clone();
+        self.membership.rng = now | 1;
+        for node in node_ids {
+            if Some(node) != me.as_ref() {
+                self.membership.members.entry(node.clone()).or_insert(Member {
+                    state: MemberState::Alive,
+                    incarnation: 0,
+                    since: now,
+                });
+            }
+        }
+    }
+
+    /// Target and piggyback for the next probe round, once `init` has run.
+    /// Also confirms any suspects whose timeout has passed.
+    pub fn next_probe(&mut self) -> Option<(String, Vec<MemberUpdate>)> {
+        self._node_id.as_ref()?;
+        self.expire_suspects();
+        let target = self.probe_target()?;
+        Some((target, self.piggyback()))
+    }
+
+    /// Next member to probe, cycling through a freshly shuffled order each round.
+    pub fn probe_target(&mut self) -> Option<String> {
+        if self.membership.next_probe >= self.membership.probe_order.len() {
+            let mut order: Vec<String> = self
+                .membership
+                .members
+                .iter()
+                .filter(|(_, member)| member.state != MemberState::Dead)
+                .map(|(node, _)| node.clone())
+                .collect();
+            order.sort();
+            self.shuffle(&mut order);
+            self.membership.probe_order = order;
+            self.membership.next_probe = 0;
+        }
+        let target = self.membership.probe_order.get(self.membership.next_probe).cloned();
+        self.membership.next_probe += 1;
+        target
+    }
+
+    /// Up to `k` live members other than `target` to relay an indirect probe.
+    pub fn indirect_helpers(&mut self, target: &str, k: usize) -> Vec<String> {
+        let mut helpers: Vec<String> = self
+            .membership
+            .members
+            .iter()
+            .filter(|(node, member)| *node != target && member.state == MemberState::Alive)
+            .map(|(node, _)| node.clone())
+            .collect();
+        helpers.sort();
+        self.shuffle(&mut helpers);
+        helpers.truncate(k);
+        helpers
+    }
+
+    /// Updates to attach to the next outgoing probe or ack.
+    pub fn piggyback(&mut self) -> Vec<MemberUpdate> {
+        let mut updates = Vec::new();
+        let mut remaining = VecDeque::new();
+        while let Some((update, sends)) = self.membership.broadcasts.pop_front() {
+            if updates.len() < MAX_PIGGYBACK {
+                updates.push(update.clone());
+                if sends > 1 {
+                    remaining.push_back((update, sends - 1));
+                }
+            } else {
+                remaining.push_back((update, sends));
+            }
+        }
+        self.membership.broadcasts = remaining;
+        updates
+    }
+
+    pub fn apply_member_updates(&mut self, updates: Vec<MemberUpdate>) {
+        for update in updates {
+            self.apply_member_update(update);
+        }
+    }
+
+    /// `node` answered a probe, directly or through a helper.
+    pub fn member_alive(&mut self, node: &str) {
+        let now = (self.clock)();
+        let rejoined = match self.membership.members.get_mut(node) {
+            Some(member) if member.state != MemberState::Alive => {
+                let was_dead = member.state == MemberState::Dead;
+                member.state = MemberState::Alive;
+                member.since = now;
+                was_dead
+            }
+            _ => false,
+        };
+        if rejoined {
+            self.mark_rejoining(node, now);
+        }
+    }
+
+    /// `node` missed both the direct and indirect probe.
+    pub fn suspect_member(&mut self, node: &str) {
+        let now = (self.clock)();
+        let Some(member) = self.membership.members.get_mut(node) else {
+            return;
+        };
+        if member.state != MemberState::Alive {
+            return;
+        }
+        member.state = MemberState::Suspect;
+        member.since = now;
+        let update = MemberUpdate {
+            node: node.to_string(),
+            state: MemberState::Suspect,
+            incarnation: member.incarnation,
+        };
+        tracing::info!(node, "suspecting member");
+        self.enqueue_member_update(update);
+    }
+
+    /// Confirm as dead any member that has stayed suspect past the timeout.
+    pub fn expire_suspects(&mut self) {
+        let now = (self.clock)();
+        let timeout = self.config.swim_suspect_timeout_ms;
+        let expired: Vec<(String, u64)> = self
+            .membership
+            .members
+            .iter()
+            .filter(|(_, member)| member.state == MemberState::Suspect && now >= member.since + timeout)
+            .map(|(node, member)| (node.clone(), member.incarnation))
+            .collect();
+        for (node, incarnation) in expired {
+            self.apply_member_update(MemberUpdate {
+                node,
+                state: MemberState::Dead,
+                incarnation,
+            });
+        }
+    }
+
+    fn apply_member_update(&mut self, update: MemberUpdate) {
+        let now = (self.clock)();
+        if self._node_id.as_deref() == Some(update.node.as_str()) {
+            // Refute rumours of our own failure by outbidding them.
+            if update.state != MemberState::Alive && update.incarnation >= self.membership.incarnation {
+                self.membership.incarnation = update.incarnation + 1;
+                let refutation = MemberUpdate {
+                    node: update.node,
+                    state: MemberState::Alive,
+                    incarnation: self.membership.incarnation,
+                };
+                self.enqueue_member_update(refutation);
+            }
+            return;
+        }
+
+        let member = self
+            .membership
+            .members
+            .entry(update.node.clone())
+            .or_insert(Member {
+                state: MemberState::Alive,
+                incarnation: 0,
+                since: now,
+            });
+        let newer = update.incarnation > member.incarnation
+            || (update.incarnation == member.incarnation && rank(update.state) > rank(member.state));
+        if !newer {
+            return;
+        }
+
+        let previous = member.state;
+        member.state = update.state;
+        member.incarnation = update.incarnation;
+        if previous != update.state {
+            member.since = now;
+        }
+        match (previous, update.state) {
+            (MemberState::Dead, MemberState::Alive) | (MemberState::Dead, MemberState::Suspect) => {
+                self.mark_rejoining(&update.node, now)
+            }
+            (_, MemberState::Dead) => {
+                tracing::info!(node = %update.node, "member confirmed dead");
+                if self.node_status.contains_key(&update.node) {
+                    self.node_status.insert(update.node.clone(), NodeStatus::Offline(now));
+                }
+            }
+            _ => {}
+        }
+        self.enqueue_member_update(update);
+    }
+
+    /// A peer we had given up on is back: resend everything it may have missed.
+    fn mark_rejoining(&mut self, node: &str, now: u64) {
+        tracing::info!(node, "member rejoining");
+        if let Some(status) = self.node_status.get_mut(node) {
+            let offline_since = match status {
+                NodeStatus::Offline(since) => *since,
+                _ => now,
+            };
+            *status = NodeStatus::Rejoining(offline_since, now);
+            let known: Vec<u64> = self.values.keys().cloned().collect();
+            self.peer_pending.entry(node.to_string()).or_default().extend(known);
+            self.retries.reset_node(node);
+        }
+    }
+
+    fn enqueue_member_update(&mut self, update: MemberUpdate) {
+        // Retransmit each update about lambda * log(n) times, per SWIM.
+        let n = self.membership.members.len() + 1;
+        let sends = 3 * ((n as f64).log2().ceil() as usize).max(1);
+        self.membership
+            .broadcasts
+            .retain(|(queued, _)| queued.node != update.node);
+        self.membership.broadcasts.push_back((update, sends));
+    }
+
+    fn shuffle(&mut self, items: &mut [String]) {
+        for i in (1..items.len()).rev() {
+            let x = xorshift(&mut self.membership.rng);
+            items.swap(i, (x % (i as u64 + 1)) as usize);
+        }
+    }
+}
+
+fn rank(state: MemberState) -> u8 {
+    match state {
+        MemberState::Alive => 0,
+        MemberState::Suspect => 1,
+        MemberState::Dead => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    };
+
+    use super::*;
+    use crate::storage::Storage;
+
+    async fn store_with_clock(now: Arc<AtomicU64>) -> Storage {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new_with_clock(tx, move || now.load(Ordering::Relaxed));
+        store.set_id("n1").await;
+        store.init_members(&["n1".into(), "n2".into(), "n3".into()]);
+        store
+    }
+
+    #[tokio::test]
+    async fn probe_target_visits_every_member_each_round() {
+        let mut store = store_with_clock(Arc::new(AtomicU64::new(1))).await;
+
+        let mut round = vec![store.probe_target().unwrap(), store.probe_target().unwrap()];
+        round.sort();
+
+        assert_eq!(round, vec!["n2".to_string(), "n3".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn suspect_expires_to_dead_and_marks_peer_offline() {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let mut store = store_with_clock(Arc::clone(&now)).await;
+        store.update_typology(vec!["n2".into()]);
+
+        store.suspect_member("n2");
+        store.expire_suspects();
+        assert_eq!(store.membership.members["n2"].state, MemberState::Suspect);
+
+        now.fetch_add(store.config.swim_suspect_timeout_ms, Ordering::Relaxed);
+        store.expire_suspects();
+
+        assert_eq!(store.membership.members["n2"].state, MemberState::Dead);
+        assert!(matches!(store.node_status["n2"], NodeStatus::Offline(_)));
+        let updates = store.piggyback();
+        assert!(updates.contains(&MemberUpdate {
+            node: "n2".into(),
+            state: MemberState::Dead,
+            incarnation: 0,
+        }));
+    }
+
+    #[tokio::test]
+    async fn newer_incarnation_brings_dead_member_back_as_rejoining() {
+        let mut store = store_with_clock(Arc::new(AtomicU64::new(1_000))).await;
+        store.update_typology(vec!["n2".into()]);
+        store.update_values("client".into(), 5).unwrap();
+        store.apply_member_updates(vec![MemberUpdate {
+            node: "n2".into(),
+            state: MemberState::Dead,
+            incarnation: 0,
+        }]);
+        store.peer_pending.get_mut("n2").unwrap().clear();
+
+        // A stale alive rumour does not resurrect it...
+        store.apply_member_updates(vec![MemberUpdate {
+            node: "n2".into(),
+            state: MemberState::Alive,
+            incarnation: 0,
+        }]);
+        assert_eq!(store.membership.members["n2"].state, MemberState::Dead);
+
+        // ...but its own refutation does.
+        store.apply_member_updates(vec![MemberUpdate {
+            node: "n2".into(),
+            state: MemberState::Alive,
+            incarnation: 1,
+        }]);
+        assert_eq!(store.membership.members["n2"].state, MemberState::Alive);
+        assert!(matches!(store.node_status["n2"], NodeStatus::Rejoining(_, _)));
+        assert_eq!(store.peer_pending["n2"].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn suspicion_of_self_is_refuted_with_higher_incarnation() {
+        let mut store = store_with_clock(Arc::new(AtomicU64::new(1_000))).await;
+
+        store.apply_member_updates(vec![MemberUpdate {
+            node: "n1".into(),
+            state: MemberState::Suspect,
+            incarnation: 0,
+        }]);
+
+        assert_eq!(store.membership.incarnation, 1);
+        assert_eq!(
+            store.piggyback(),
+            vec![MemberUpdate {
+                node: "n1".into(),
+                state: MemberState::Alive,
+                incarnation: 1,
+            }]
+        );
+    }
+}
@@ -1,37 +1,108 @@
+pub mod actor;
+pub mod backend;
+pub mod backoff;
 pub mod cas;
+pub mod causal;
+pub mod failure_detector;
 pub mod g_counter;
+pub mod kv_store;
+pub mod membership;
 pub mod node_state;
+pub mod partition;
+pub mod snapshot;
+pub mod total_order;
 pub mod value_store;
+pub mod wal;
 
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     hash::{DefaultHasher, Hash, Hasher},
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use tokio::sync::{Mutex, mpsc::Sender};
+use tokio_util::sync::CancellationToken;
 
-use crate::{broadcast::actor::BroadcastCommand, snowflake::Snowflake};
+use anyhow::{Context, bail};
 
-use self::{cas::PendingRequest, node_state::NodeStatus};
+use crate::{
+    broadcast::actor::BroadcastCommand,
+    config::Config,
+    hlc::{Hlc, Timestamp},
+    ids::IdGenerator,
+    snowflake::{Layout, Snowflake, SnowflakeParts},
+};
+
+use self::{
+    backend::{MemoryBackend, StorageBackend, WalEntry},
+    backoff::RetrySchedule,
+    cas::PendingRequest,
+    causal::CausalState,
+    failure_detector::PhiAccrual,
+    membership::Membership,
+    node_state::NodeStatus,
+    partition::HashRing,
+    total_order::TotalOrder,
+};
 
 pub type NodeId = Arc<Mutex<Option<String>>>;
 
+/// Wait until `init` has set `id`, for background tasks whose span names the
+/// node. `None` if `shutdown` fires first.
+pub async fn wait_for_node_id(id: &NodeId, shutdown: &CancellationToken) -> Option<String> {
+    loop {
+        if let Some(id) = id.lock().await.clone() {
+            return Some(id);
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => return None,
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+        }
+    }
+}
+
 pub struct Storage {
     pub node_id: NodeId,
     _node_id: Option<String>,
+    /// Snowflake node bits, unique within the cluster once `init` has run.
+    node_number: Option<u64>,
+    /// Whether `node_number` is our index in `node_ids`, in which case every
+    /// cluster member's number is its index there.
+    numbered_by_position: bool,
+    /// Every node in the cluster, as given by `init`.
+    pub node_ids: Vec<String>,
     pub topology: HashSet<String>,
     pub values: BTreeMap<u64, (String, u64)>,
     pub peer_pending: BTreeMap<String, BTreeSet<u64>>,
+    pub retries: RetrySchedule,
     pending_cas: HashMap<u64, PendingRequest>,
 
-    pub snowflake: Snowflake, // ...
+    pub snowflake: Arc<Snowflake>,
+    /// Generator for client-facing IDs, installed by `init`.
+    ids: Option<Box<dyn IdGenerator>>,
+    /// Snowflake timestamps up to here are covered by a logged reservation.
+    id_reserved_until: u64,
     pub node_status: HashMap<String, NodeStatus>,
+    pub detectors: HashMap<String, PhiAccrual>,
+    pub membership: Membership,
+    pub causal: CausalState,
+    pub total: TotalOrder,
+    /// Key placement over `node_ids`, built by `init`.
+    pub ring: HashRing,
     pub workload: Option<String>,
     clock: Arc<dyn Fn() -> u64 + Send + Sync>,
+    /// Shared with the stdout writer, which stamps every inter-node message,
+    /// and with the dispatcher through the RPC client, which observes them.
+    pub hlc: Arc<Hlc>,
     pub counter: HashMap<String, u64>,
+    /// Peers that have not acked the latest counter map, with the msg_id of
+    /// the last copy sent to each; resent on the gossip tick.
+    pub counter_unacked: HashMap<String, u64>,
+    pub registers: HashMap<String, u64>,
     pub tx: Sender<BroadcastCommand>,
+    backend: Box<dyn StorageBackend>,
+    pub config: Config,
 }
 
 impl Storage {
@@ -39,53 +110,191 @@ impl Storage {
     where
         F: Fn() -> u64 + Send + Sync + 'static,
     {
-        let storage = Self {
+        Self::build(tx, clock, Config::default(), Layout::default())
+    }
+
+    /// Fails if `config` would not pass `Config::validate`, e.g. when it was
+    /// built in code rather than loaded.
+    pub fn new_with_config(tx: Sender<BroadcastCommand>, config: Config) -> anyhow::Result<Self> {
+        config.validate()?;
+        let layout = config.snowflake_layout()?;
+        Ok(Self::build(tx, time_now, config, layout))
+    }
+
+    fn build<F>(tx: Sender<BroadcastCommand>, clock: F, config: Config, layout: Layout) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        let clock: Arc<dyn Fn() -> u64 + Send + Sync> = Arc::new(clock);
+        Self {
             node_id: Arc::new(Mutex::new(None)),
             _node_id: None,
+            node_number: None,
+            numbered_by_position: false,
+            node_ids: Vec::new(),
             topology: HashSet::new(),
             values: BTreeMap::new(),
             peer_pending: BTreeMap::new(),
+            retries: RetrySchedule::default(),
             pending_cas: HashMap::new(),
-            snowflake: Snowflake::new(),
+            snowflake: Arc::new(Snowflake::new(
+                config.snowflake_epoch_ms,
+                layout,
+                config.snowflake_max_borrow_ms,
+            )),
+            ids: None,
+            id_reserved_until: 0,
             node_status: HashMap::new(),
-            clock: Arc::new(clock),
+            detectors: HashMap::new(),
+            membership: Membership::default(),
+            causal: CausalState::default(),
+            total: TotalOrder::default(),
+            ring: HashRing::default(),
+            hlc: Arc::new(Hlc::new(clock.clone(), config.hlc_max_offset_ms)),
+            clock,
             counter: HashMap::new(),
+            counter_unacked: HashMap::new(),
+            registers: HashMap::new(),
             workload: Some("".into()),
             tx,
-        };
-        storage
+            backend: Box::new(MemoryBackend),
+            config,
+        }
     }
 
     pub fn new(tx: Sender<BroadcastCommand>) -> Self {
-        Self::new_with_clock(tx, || time_now())
+        Self::new_with_clock(tx, time_now)
     }
 
     pub async fn set_id(&mut self, id: &str) {
         self._node_id = Some(id.to_string());
+        self.node_number = parse_node_number(id);
+        self.numbered_by_position = false;
         *self.node_id.lock().await = Some(id.to_string());
     }
 
+    /// Give this node the snowflake number matching its position in
+    /// `node_ids`, or the `N` in `nN` if it is not listed. Fails if the
+    /// number does not fit in the snowflake node bits.
+    pub fn assign_node_number(&mut self, node_ids: &[String]) -> anyhow::Result<()> {
+        let node_id = self._node_id.as_ref().context("Node id has not been set")?;
+        let max_node = self.snowflake.layout().max_node();
+        if node_ids.len() as u64 > max_node + 1 {
+            bail!(
+                "Cluster of {} nodes exceeds the {} snowflake node numbers",
+                node_ids.len(),
+                max_node + 1
+            );
+        }
+        let position = node_ids.iter().position(|id| id == node_id);
+        let number = match position {
+            Some(index) => index as u64,
+            None => parse_node_number(node_id)
+                .with_context(|| format!("Cannot derive a node number for {}", node_id))?,
+        };
+        if number > max_node {
+            bail!("Node number {} for {} exceeds {}", number, node_id, max_node);
+        }
+        self.node_number = Some(number);
+        self.numbered_by_position = position.is_some();
+        Ok(())
+    }
+
+    /// Falls back to a hash of the name for ids assigned outside `init`
+    /// that are not of the form `nN`; those may collide.
     fn node_id_to_u64(&self) -> u64 {
+        if let Some(number) = self.node_number {
+            return number;
+        }
         let node_id = self._node_id.as_ref().expect("Node Id not set");
         let mut hasher = DefaultHasher::new();
         node_id.hash(&mut hasher);
         hasher.finish()
     }
 
-    pub fn next_id(&self) -> u64 {
-        let id = self.node_id_to_u64();
-        self.snowflake.next_id(id)
+    /// IDs never sort before anything this node has already seen.
+    pub fn next_id(&mut self) -> u64 {
+        let node = self.node_id_to_u64();
+        let id = self.snowflake.next_id_after(node, self.hlc.peek().physical());
+        if let Err(e) = self.reserve_ids() {
+            tracing::error!(error = %e, "failed to log id reservation");
+        }
+        id
+    }
+
+    /// Which node and time produced `key`, for logs and debugging. Names the
+    /// node only when numbers are positions in `node_ids`; otherwise the
+    /// number may belong to a node listed elsewhere or not at all.
+    pub fn key_provenance(&self, key: u64) -> String {
+        let parts: SnowflakeParts = self.snowflake.decode(key);
+        let node = self
+            .node_ids
+            .get(parts.node as usize)
+            .filter(|_| self.numbered_by_position);
+        match node {
+            Some(node) => format!("{} ({})", parts, node),
+            None => parts.to_string(),
+        }
+    }
+
+    /// Set up the configured `IdScheme` for this node.
+    pub fn install_id_generator(&mut self) {
+        let node_id = self._node_id.clone().expect("Node Id not set");
+        let generator = self.config.id_scheme.build(
+            &node_id,
+            self.node_id_to_u64(),
+            Arc::clone(&self.snowflake),
+            Arc::clone(&self.hlc),
+        );
+        self.ids = Some(generator);
+    }
+
+    /// ID for a `generate` request, from the configured scheme.
+    pub fn generate_id(&mut self) -> anyhow::Result<String> {
+        let id = self
+            .ids
+            .as_ref()
+            .context("Node has not been initialised")?
+            .next_id()?;
+        self.reserve_ids()?;
+        Ok(id)
+    }
+
+    /// With `id_reserve_ms` set, log a snowflake timestamp high-water mark
+    /// that far ahead whenever IDs pass the previous one. IDs are only sent
+    /// after this returns, so after a restart the generator can resume past
+    /// everything it may have issued, whatever the clock says. Fails if the
+    /// mark could not be logged; the next call tries again.
+    fn reserve_ids(&mut self) -> anyhow::Result<()> {
+        let block = self.config.id_reserve_ms;
+        let last = self.snowflake.last_timestamp();
+        if block == 0 || last < self.id_reserved_until {
+            return Ok(());
+        }
+        self.try_persist(WalEntry::IdReservation { until_ms: last + block })?;
+        self.id_reserved_until = last + block;
+        Ok(())
+    }
+
+    /// Hybrid logical timestamp for a local event, e.g. a last-writer-wins
+    /// write or a transaction commit.
+    pub fn timestamp(&self) -> Timestamp {
+        self.hlc.now()
     }
 
     pub fn values(&self) -> Vec<u64> {
         self.values
             .values()
-            .map(|(_, v)| v.clone())
+            .map(|(_, v)| *v)
             .collect::<Vec<u64>>()
     }
 }
 
-fn time_now() -> u64 {
+fn parse_node_number(id: &str) -> Option<u64> {
+    id.strip_prefix('n')?.parse().ok()
+}
+
+pub(crate) fn time_now() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards")
@@ -117,6 +326,72 @@ mod tests {
         assert_eq!(node_id_guard.as_deref(), Some("node-A"));
     }
 
+    #[tokio::test]
+    async fn node_numbers_are_unique_across_1024_nodes() {
+        let node_ids: Vec<String> = (0..1024).map(|i| format!("n{}", i + 1)).collect();
+        let mut ids = HashSet::new();
+        let mut numbers = HashSet::new();
+        for node in &node_ids {
+            let (tx, _rx) = tokio::sync::mpsc::channel(1);
+            let mut store = Storage::new(tx);
+            store.set_id(node).await;
+            store.assign_node_number(&node_ids).unwrap();
+            assert!(numbers.insert(store.node_id_to_u64()));
+            for _ in 0..4 {
+                assert!(ids.insert(store.next_id()), "duplicate id from {}", node);
+            }
+        }
+        assert_eq!(numbers.len(), 1024);
+    }
+
+    #[tokio::test]
+    async fn key_provenance_names_the_issuing_node() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let mut store = Storage::new(tx);
+        store.set_id("n2").await;
+        store.node_ids = vec!["n1".into(), "n2".into()];
+        store.assign_node_number(&store.node_ids.clone()).unwrap();
+
+        let key = store.next_id();
+        let parts = store.snowflake.decode(key);
+        assert_eq!(parts.node, 1);
+        assert!(store.key_provenance(key).ends_with("(n2)"));
+    }
+
+    #[test]
+    fn new_with_config_rejects_an_unusable_layout() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let config = Config {
+            snowflake_node_bits: 40,
+            ..Config::default()
+        };
+        assert!(Storage::new_with_config(tx, config).is_err());
+    }
+
+    #[tokio::test]
+    async fn key_provenance_does_not_guess_fallback_numbers() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let mut store = Storage::new(tx);
+        store.set_id("n1").await;
+        store.node_ids = vec!["n0".into(), "n9".into()];
+        store.assign_node_number(&["n0".into()]).unwrap();
+
+        let key = store.next_id();
+        assert_eq!(store.snowflake.decode(key).node, 1);
+        assert!(!store.key_provenance(key).contains("n9"));
+    }
+
+    #[tokio::test]
+    async fn oversized_cluster_is_rejected() {
+        let node_ids: Vec<String> = (0..1025).map(|i| format!("n{}", i)).collect();
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let mut store = Storage::new(tx);
+        store.set_id("n0").await;
+
+        assert!(store.assign_node_number(&node_ids).is_err());
+        assert!(store.assign_node_number(&node_ids[..1024]).is_ok());
+    }
+
     #[tokio::test]
     async fn update_typology_inserts_nodes_and_marks_online() {
         let (tx, _rx) = tokio::sync::mpsc::channel(10);
@@ -143,7 +418,7 @@ mod tests {
 
         store.update_typology(vec!["node-B".into(), "node-C".into()]);
 
-        store.update_values("node-A".to_string(), 42);
+        store.update_values("node-A".to_string(), 42).unwrap();
 
         let key = *store.values.keys().next().unwrap();
         let val = store.values.get(&key).unwrap();
@@ -166,7 +441,7 @@ mod tests {
         store.set_id("node-A").await;
 
         store.update_typology(vec!["node-B".into()]);
-        store.update_values("node-A".to_string(), 123);
+        store.update_values("node-A".to_string(), 123).unwrap();
 
         let key = *store.values.keys().next().unwrap();
 
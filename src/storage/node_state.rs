@@ -1,10 +1,14 @@
+use serde::{Deserialize, Serialize};
 
+use super::failure_detector::{PhiAccrual, Suspicion};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeStatus {
     Online(u64),
     Offline(u64),
+    /// Back after being offline: (offline since, rejoined at). Becomes
+    /// `Online` on the first ack.
     Rejoining(u64, u64),
-    // Add others if needed (e.g., Suspected, Failed)
 }
 
 impl super::Storage {
@@ -12,10 +16,12 @@ impl super::Storage {
         let to_update = update.clone();
         for node in update {
             if self.topology.insert(node.clone()) {
+                let now = (self.clock)();
                 self.node_status
-                    .insert(node.clone(), NodeStatus::Online((self.clock)()));
+                    .insert(node.clone(), NodeStatus::Online(now));
                 self.peer_pending
                     .insert(node.clone(), self.values.keys().cloned().collect());
+                self.detector(&node).resume(now);
             }
         }
 
@@ -30,12 +36,16 @@ impl super::Storage {
             self.topology.remove(&node);
             self.node_status.remove(&node);
             self.peer_pending.remove(&node);
+            self.retries.reset_node(&node);
+            self.detectors.remove(&node);
         }
     }
 
+    /// Peers gossiped to at the online cadence, including ones catching up
+    /// after a rejoin.
     pub fn online_nodes(&self) -> impl Iterator<Item = &String> {
         self.node_status.iter().filter_map(|(name, status)| {
-            if matches!(status, NodeStatus::Online(_)) {
+            if matches!(status, NodeStatus::Online(_) | NodeStatus::Rejoining(_, _)) {
                 Some(name)
             } else {
                 None
@@ -53,16 +63,65 @@ impl super::Storage {
         })
     }
 
+    /// Online peers the failure detector is starting to doubt. They are
+    /// retried at the offline cadence until they ack again.
+    pub fn suspected_nodes(&self) -> impl Iterator<Item = &String> {
+        self.online_nodes()
+            .filter(|node| self.suspicion(node) == Suspicion::Suspect)
+    }
+
     pub fn update_node_states(&mut self) {
         let now = (self.clock)();
-        for (_name, status) in self.node_status.iter_mut() {
-            if let NodeStatus::Online(time) = status {
-                if now > *time + 30_000 {
-                    *status = NodeStatus::Offline(now);
-                }
+        let dead: Vec<String> = self
+            .online_nodes()
+            .filter(|node| self.suspicion(node) == Suspicion::Dead)
+            .cloned()
+            .collect();
+        for node in dead {
+            self.node_status.insert(node, NodeStatus::Offline(now));
+        }
+    }
+
+    /// Current phi for `node`, if it owes us acks and we have enough history.
+    pub fn phi(&self, node: &str) -> Option<f64> {
+        let awaiting_ack = self
+            .peer_pending
+            .get(node)
+            .is_some_and(|pending| !pending.is_empty());
+        if !awaiting_ack {
+            return None;
+        }
+        self.detectors.get(node)?.phi((self.clock)())
+    }
+
+    pub fn suspicion(&self, node: &str) -> Suspicion {
+        let has_history = self
+            .detectors
+            .get(node)
+            .is_some_and(PhiAccrual::has_history);
+        if has_history {
+            return match self.phi(node) {
+                Some(phi) if phi >= self.config.phi_offline => Suspicion::Dead,
+                Some(phi) if phi >= self.config.phi_suspect => Suspicion::Suspect,
+                _ => Suspicion::Alive,
+            };
+        }
+        // Not enough acks yet to judge; fall back to the fixed timeout.
+        match self.node_status.get(node) {
+            Some(NodeStatus::Online(time)) if (self.clock)() > *time + self.config.offline_after_ms => {
+                Suspicion::Dead
             }
+            Some(NodeStatus::Offline(_)) => Suspicion::Dead,
+            _ => Suspicion::Alive,
         }
     }
+
+    pub(crate) fn detector(&mut self, node: &str) -> &mut PhiAccrual {
+        let (window, min_std_dev_ms) = (self.config.phi_window, self.config.phi_min_std_dev_ms);
+        self.detectors
+            .entry(node.to_string())
+            .or_insert_with(|| PhiAccrual::new(window, min_std_dev_ms))
+    }
 }
 
 #[cfg(test)]
@@ -106,4 +165,42 @@ mod tests {
             Some(NodeStatus::Offline(_))
         ));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn silent_peer_is_suspected_then_marked_offline() {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicU64, Ordering},
+        };
+
+        let now = Arc::new(AtomicU64::new(1_000));
+        let clock = Arc::clone(&now);
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new_with_clock(tx, move || clock.load(Ordering::Relaxed));
+        store.set_id("node-A").await;
+        store.update_typology(vec!["node-B".into()]);
+
+        // node-B acks every 100ms for a while.
+        for _ in 0..20 {
+            store.update_values("client".into(), 1).unwrap();
+            let key = *store.values.keys().last().unwrap();
+            now.fetch_add(100, Ordering::Relaxed);
+            store.remove_from_peer_pending("node-B".into(), key);
+        }
+        store.update_values("client".into(), 2).unwrap();
+        assert_eq!(store.suspicion("node-B"), Suspicion::Alive);
+        assert_eq!(store.gossip_batch(true).len(), 1);
+
+        now.fetch_add(550, Ordering::Relaxed);
+        assert_eq!(store.suspicion("node-B"), Suspicion::Suspect);
+        assert!(store.gossip_batch(true).is_empty());
+
+        now.fetch_add(1_000, Ordering::Relaxed);
+        store.update_node_states();
+        assert_eq!(store.gossip_batch(false).len(), 1);
+        assert!(matches!(
+            store.node_status.get("node-B"),
+            Some(NodeStatus::Offline(_))
+        ));
+    }
+}
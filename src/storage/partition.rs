@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeMap, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// Consistent-hash ring mapping keys to owner nodes. Each node is placed at
+/// `vnodes` points so keys spread evenly, and adding or removing a node only
+/// moves the keys next to its points.
+///
+/// Positions come from `DefaultHasher`, which is stable within one build;
+/// every node of a cluster runs the same binary, so they agree on the ring.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+    nodes: usize,
+}
+
+impl HashRing {
+    pub fn new(nodes: &[String], vnodes: usize) -> Self {
+        let mut points = BTreeMap::new();
+        for node in nodes {
+            for vnode in 0..vnodes {
+                points.entry(position(&(node, vnode))).or_insert_with(|| node.clone());
+            }
+        }
+        Self {
+            points,
+            nodes: nodes.len(),
+        }
+    }
+
+    /// Node responsible for `key`, or `None` before membership is known.
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        self.replicas(key, 1).into_iter().next()
+    }
+
+    /// Up to `n` distinct nodes for `key`, owner first, taken clockwise
+    /// from the key's position.
+    pub fn replicas(&self, key: &str, n: usize) -> Vec<&str> {
+        let n = n.min(self.nodes);
+        let start = position(&key);
+        let mut replicas: Vec<&str> = Vec::with_capacity(n);
+        for node in self.points.range(start..).chain(self.points.range(..start)).map(|(_, node)| node) {
+            if replicas.len() == n {
+                break;
+            }
+            if !replicas.contains(&node.as_str()) {
+                replicas.push(node);
+            }
+        }
+        replicas
+    }
+}
+
+fn position<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Storage {
+    /// Nodes that would hold `key`, owner first; `Config::replication_factor`
+    /// long once `init` has built the ring. Only the owner stores it today.
+    pub fn replicas_of(&self, key: &str) -> Vec<&str> {
+        self.ring.replicas(key, self.config.replication_factor)
+    }
+
+    pub fn owner_of(&self, key: &str) -> Option<&str> {
+        self.ring.owner(key)
+    }
+
+    /// The owner of `key` when that is some other node; `None` when it is
+    /// this node or the ring is not built yet.
+    pub fn remote_owner_of(&self, key: &str) -> Option<&str> {
+        self.owner_of(key).filter(|owner| Some(*owner) != self._node_id.as_deref())
+    }
+
+    /// Whether this node is in `key`'s replica list.
+    pub fn holds(&self, key: &str) -> bool {
+        let Some(node_id) = self._node_id.as_deref() else {
+            return false;
+        };
+        self.replicas_of(key).contains(&node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn nodes(n: usize) -> Vec<String> {
+        (1..=n).map(|i| format!("n{}", i)).collect()
+    }
+
+    #[test]
+    fn keys_spread_across_nodes() {
+        let ring = HashRing::new(&nodes(5), 64);
+        let mut owned = HashMap::new();
+        for key in 0..10_000 {
+            *owned.entry(ring.owner(&key.to_string()).unwrap()).or_insert(0) += 1;
+        }
+
+        assert_eq!(owned.len(), 5);
+        assert!(owned.values().all(|count| (1_000..3_000).contains(count)), "{:?}", owned);
+    }
+
+    #[test]
+    fn removing_a_node_only_moves_its_keys() {
+        let before = HashRing::new(&nodes(5), 64);
+        let after = HashRing::new(&nodes(4), 64);
+
+        for key in (0..2_000).map(|key| key.to_string()) {
+            let owner = before.owner(&key).unwrap();
+            if owner != "n5" {
+                assert_eq!(after.owner(&key), Some(owner));
+            }
+        }
+    }
+
+    #[test]
+    fn replicas_are_distinct_and_start_with_the_owner() {
+        let ring = HashRing::new(&nodes(3), 16);
+
+        let replicas = ring.replicas("k", 2);
+        assert_eq!(replicas.len(), 2);
+        assert_ne!(replicas[0], replicas[1]);
+        assert_eq!(Some(replicas[0]), ring.owner("k"));
+        assert_eq!(ring.replicas("k", 10).len(), 3);
+        assert!(HashRing::default().owner("k").is_none());
+    }
+
+    #[tokio::test]
+    async fn storage_holds_keys_it_replicates() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        // `validate` refuses this until replicas are synced; the list math
+        // itself is still what routing would build on.
+        store.config.replication_factor = 3;
+        store.set_id("n2").await;
+        store.ring = HashRing::new(&nodes(3), 16);
+
+        assert!((0..50).all(|key| store.holds(&key.to_string())));
+    }
+}
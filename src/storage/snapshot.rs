@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Storage,
+    cas::PendingRequest,
+    causal::CausalState,
+    membership::Membership,
+    node_state::NodeStatus,
+    partition::HashRing,
+    total_order::TotalOrder,
+};
+
+/// Everything a node knows, in a form that can be dumped from a live run and
+/// loaded back into a fresh `Storage` to reproduce it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    pub node_id: Option<String>,
+    pub workload: Option<String>,
+    pub topology: HashSet<String>,
+    pub values: BTreeMap<u64, (String, u64)>,
+    pub peer_pending: BTreeMap<String, BTreeSet<u64>>,
+    pub pending_cas: HashMap<u64, PendingRequest>,
+    pub node_status: HashMap<String, NodeStatus>,
+    pub counter: HashMap<String, u64>,
+    pub registers: HashMap<String, u64>,
+    #[serde(default)]
+    pub node_ids: Vec<String>,
+    #[serde(default)]
+    pub ring: HashRing,
+    #[serde(default)]
+    pub membership: Membership,
+    #[serde(default)]
+    pub causal: CausalState,
+    #[serde(default)]
+    pub total: TotalOrder,
+    #[serde(default)]
+    pub id_reserved_until: u64,
+}
+
+impl Storage {
+    pub fn export_snapshot(&self) -> NodeSnapshot {
+        NodeSnapshot {
+            node_id: self._node_id.clone(),
+            workload: self.workload.clone(),
+            topology: self.topology.clone(),
+            values: self.values.clone(),
+            peer_pending: self.peer_pending.clone(),
+            pending_cas: self.pending_cas.clone(),
+            node_status: self.node_status.clone(),
+            counter: self.counter.clone(),
+            registers: self.registers.clone(),
+            node_ids: self.node_ids.clone(),
+            ring: self.ring.clone(),
+            membership: self.membership.clone(),
+            causal: self.causal.clone(),
+            total: self.total.clone(),
+            id_reserved_until: self.id_reserved_until,
+        }
+    }
+
+    /// Replace this node's state with `snapshot`, numbering the node as
+    /// `init` would have. Nothing is written to the backend.
+    pub async fn import_snapshot(&mut self, snapshot: NodeSnapshot) -> anyhow::Result<()> {
+        if let Some(id) = &snapshot.node_id {
+            self.set_id(id).await;
+            if !snapshot.node_ids.is_empty() {
+                self.assign_node_number(&snapshot.node_ids)?;
+            }
+            self.install_id_generator();
+        }
+        self.node_ids = snapshot.node_ids;
+        self.ring = snapshot.ring;
+        self.membership = snapshot.membership;
+        self.causal = snapshot.causal;
+        self.total = snapshot.total;
+        self.resume_ids_after(snapshot.id_reserved_until);
+        self.workload = snapshot.workload;
+        self.topology = snapshot.topology;
+        self.values = snapshot.values;
+        self.peer_pending = snapshot.peer_pending;
+        self.pending_cas = snapshot.pending_cas;
+        self.node_status = snapshot.node_status;
+        self.counter = snapshot.counter;
+        self.registers = snapshot.registers;
+        Ok(())
+    }
+
+    pub fn snapshot_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&self.export_snapshot())?)
+    }
+
+    pub async fn load_snapshot_json(&mut self, json: &str) -> anyhow::Result<()> {
+        let snapshot = serde_json::from_str(json)?;
+        self.import_snapshot(snapshot).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{
+        Storage,
+        causal::{CausalTag, VectorClock},
+        partition::HashRing,
+    };
+
+    #[tokio::test]
+    async fn snapshot_round_trips_through_json() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.set_id("node-A").await;
+        store.update_typology(vec!["node-B".into()]);
+        store.update_values("client".into(), 5).unwrap();
+        store.counter.insert("node-A".into(), 3);
+        store.kv_write("k".into(), 8).unwrap();
+        let node_ids: Vec<String> = vec!["node-A".into(), "node-B".into()];
+        store.assign_node_number(&node_ids).unwrap();
+        store.init_members(&node_ids);
+        store.causal.delivered = VectorClock::over(&node_ids);
+        store.ring = HashRing::new(&node_ids, 4);
+        store.node_ids = node_ids;
+        store.broadcast_causal("client".into(), 6, None).unwrap();
+        let ahead = CausalTag {
+            origin: "node-B".into(),
+            clock: VectorClock([("node-B".to_string(), 2)].into()),
+        };
+        store.broadcast_causal("node-B".into(), 7, Some(ahead)).unwrap();
+        store.total.promised = 3;
+        store.total.outbox.insert(11, 12);
+        store.id_reserved_until = 1_000;
+
+        let json = store.snapshot_json().unwrap();
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut restored = Storage::new(tx);
+        restored.load_snapshot_json(&json).await.unwrap();
+
+        assert_eq!(restored.export_snapshot(), store.export_snapshot());
+        assert_eq!(restored.causal.buffered.len(), 1);
+        assert_eq!(restored.membership.members.len(), 1);
+        assert_eq!(restored.node_id.lock().await.as_deref(), Some("node-A"));
+    }
+}
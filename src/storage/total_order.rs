@@ -0,0 +1,573 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{broadcast::actor::BroadcastCommand, message::BroadcastMessage};
+
+use super::{Storage, membership::MemberState};
+
+/// Submissions remembered for retry dedupe; older ones are forgotten first.
+const ACCEPTED_CAPACITY: usize = 65_536;
+
+/// Sequencer-based atomic broadcast, used when
+/// `Config::total_order_broadcast` is set.
+///
+/// The sequencer is the lowest node id SWIM does not consider dead. Other
+/// nodes submit client values to it and retry until acked; it numbers each
+/// value and the numbered values spread through normal gossip. Every node
+/// delivers in sequence order, so all reads list the same prefix.
+///
+/// Sequencers are fenced by epochs. Before numbering anything, a new
+/// sequencer picks an epoch above any it has seen and asks every node to
+/// promise it; nodes that promise drop values stamped with older epochs and
+/// report the sequenced values they hold. With promises from a majority it
+/// adopts every reported value, restamps its log with the new epoch, fills
+/// positions nobody reported with no-ops and continues after the highest
+/// number. An old sequencer cut off in a
+/// minority can still number values that its side delivers; when the two
+/// sides meet, the conflicting positions are logged and counted in
+/// `conflicts`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TotalOrder {
+    /// Every sequenced value seen so far, by sequence number.
+    pub log: BTreeMap<u64, LogEntry>,
+    /// Highest sequence number such that it and all before it are known.
+    pub delivered: u64,
+    /// Our clients' values waiting for the sequencer to ack, by submission id.
+    pub outbox: BTreeMap<u64, u64>,
+    /// Position of each sequenced value, keyed like `Storage::values`.
+    pub seqs: BTreeMap<u64, Sequenced>,
+    /// Highest epoch promised or seen; values from older epochs are dropped.
+    pub promised: u64,
+    /// Our epoch once a majority has promised it and we may number values.
+    pub epoch: Option<u64>,
+    /// Positions seen with two different values; each one means nodes may
+    /// have delivered different logs.
+    pub conflicts: u64,
+    campaign: Option<Campaign>,
+    /// (src, msg_id) of submissions already accepted, so retries are ignored.
+    accepted: HashSet<(String, u64)>,
+    accepted_order: VecDeque<(String, u64)>,
+}
+
+/// Where a value sits in the total order, and which sequencer put it there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sequenced {
+    pub epoch: u64,
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub epoch: u64,
+    /// `None` for a no-op: a position a new sequencer found empty and closed
+    /// so delivery can move past it.
+    pub value: Option<u64>,
+    /// Key of the value in `Storage::values`, or the no-op's own key.
+    key: u64,
+}
+
+/// A sequencer-in-waiting collecting promises for `epoch`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Campaign {
+    epoch: u64,
+    granted: BTreeSet<String>,
+    /// Highest-epoch value reported for each position.
+    recovered: BTreeMap<u64, (u64, u64)>,
+}
+
+impl TotalOrder {
+    /// Delivered values, in delivery order.
+    pub fn values(&self) -> Vec<u64> {
+        self.log.range(..=self.delivered).filter_map(|(_, entry)| entry.value).collect()
+    }
+
+    fn advance(&mut self) {
+        while self.log.contains_key(&(self.delivered + 1)) {
+            self.delivered += 1;
+        }
+    }
+
+    /// Remember a submission; false if it was already accepted.
+    fn accept(&mut self, src: String, msg_id: u64) -> bool {
+        if !self.accepted.insert((src.clone(), msg_id)) {
+            return false;
+        }
+        self.accepted_order.push_back((src, msg_id));
+        if self.accepted_order.len() > ACCEPTED_CAPACITY
+            && let Some(oldest) = self.accepted_order.pop_front()
+        {
+            self.accepted.remove(&oldest);
+        }
+        true
+    }
+
+    /// Drop a submission accepted by `accept` that could not be numbered.
+    fn forget(&mut self, src: &str, msg_id: u64) {
+        self.accepted.remove(&(src.to_string(), msg_id));
+    }
+
+    /// Entries after `after`, as reported in a promise. No-ops are left out;
+    /// the next sequencer closes the same positions again.
+    pub fn entries_after(&self, after: u64) -> Vec<(u64, u64, u64)> {
+        self.log
+            .range(after + 1..)
+            .filter_map(|(seq, entry)| entry.value.map(|value| (*seq, entry.epoch, value)))
+            .collect()
+    }
+
+    /// Whether `key` names a no-op rather than a value.
+    pub fn is_noop(&self, key: u64) -> bool {
+        self.seqs
+            .get(&key)
+            .and_then(|tag| self.log.get(&tag.seq))
+            .is_some_and(|entry| entry.key == key && entry.value.is_none())
+    }
+}
+
+impl Storage {
+    /// Handle a `broadcast` in total-order mode. `seq` is set when the value
+    /// has already been numbered by a sequencer.
+    pub fn broadcast_total(
+        &mut self,
+        src: String,
+        msg_id: u64,
+        value: u64,
+        seq: Option<Sequenced>,
+    ) -> anyhow::Result<()> {
+        if let Some(seq) = seq {
+            return self.learn_sequenced(src, seq, Some(value));
+        }
+        if !self.total.accept(src.clone(), msg_id) {
+            return Ok(());
+        }
+        match self.active_epoch() {
+            Some(epoch) => {
+                let seq = self.total.log.keys().next_back().map_or(1, |last| last + 1);
+                let me = self._node_id.clone().expect("Node Id not set");
+                let learned = self.learn_sequenced(me, Sequenced { epoch, seq }, Some(value));
+                if learned.is_err() {
+                    // Not numbered after all, so the submitter's retry must be.
+                    self.total.forget(&src, msg_id);
+                }
+                learned
+            }
+            None => {
+                let id = self.next_id();
+                self.total.outbox.insert(id, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Current sequencer, once `init` has told us the cluster.
+    pub fn sequencer(&self) -> Option<&String> {
+        let me = self._node_id.as_ref();
+        self.node_ids
+            .iter()
+            .filter(|node| {
+                Some(*node) == me
+                    || self
+                        .membership
+                        .members
+                        .get(*node)
+                        .is_none_or(|member| member.state != MemberState::Dead)
+            })
+            .min()
+    }
+
+    pub fn is_sequencer(&self) -> bool {
+        self.sequencer().is_some() && self.sequencer() == self._node_id.as_ref()
+    }
+
+    /// Our epoch, if we are the sequencer and nobody has promised a newer one.
+    fn active_epoch(&self) -> Option<u64> {
+        self.total
+            .epoch
+            .filter(|epoch| *epoch >= self.total.promised && self.is_sequencer())
+    }
+
+    /// Unacked submissions due for (re)sending to the sequencer, or, on the
+    /// sequencer, promise requests for an epoch it does not hold yet.
+    pub(crate) fn total_order_submissions(&mut self, now: u64) -> Vec<BroadcastCommand> {
+        if self.is_sequencer() {
+            if self.active_epoch().is_none() {
+                return self.campaign();
+            }
+            // We became sequencer with submissions still queued: number them.
+            let outbox = std::mem::take(&mut self.total.outbox);
+            let me = self._node_id.clone().expect("Node Id not set");
+            for (id, value) in outbox {
+                if let Err(e) = self.broadcast_total(me.clone(), id, value, None) {
+                    tracing::error!(error = %e, "queued submission was not logged; keeping it");
+                    self.total.outbox.insert(id, value);
+                }
+            }
+            return Vec::new();
+        }
+        self.total.campaign = None;
+        let Some(sequencer) = self.sequencer().cloned() else {
+            return Vec::new();
+        };
+        let (base, cap, jitter) = (
+            self.config.gossip_interval_ms,
+            self.config.gossip_backoff_max_ms,
+            self.config.gossip_backoff_jitter,
+        );
+        let due: Vec<(u64, u64)> = self
+            .total
+            .outbox
+            .iter()
+            .filter(|(id, _)| self.retries.is_due(&sequencer, **id, now))
+            .map(|(id, value)| (*id, *value))
+            .collect();
+        due.into_iter()
+            .map(|(msg_id, value)| {
+                self.retries.record_send(&sequencer, msg_id, now, base, cap, jitter);
+                BroadcastCommand::Broadcast {
+                    dest: sequencer.clone(),
+                    msg_id,
+                    message: BroadcastMessage::Single(value),
+                    causal: None,
+                    seq: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Start or continue asking for promises; re-sent every online gossip
+    /// tick to whoever has not granted yet.
+    fn campaign(&mut self) -> Vec<BroadcastCommand> {
+        let stale = self
+            .total
+            .campaign
+            .as_ref()
+            .is_none_or(|campaign| campaign.epoch < self.total.promised);
+        if stale {
+            let epoch = self.next_epoch();
+            let me = self._node_id.clone().expect("Node Id not set");
+            self.total.promised = epoch;
+            self.total.campaign = Some(Campaign {
+                epoch,
+                granted: BTreeSet::from([me]),
+                recovered: BTreeMap::new(),
+            });
+            tracing::info!(epoch, "campaigning to become sequencer");
+            self.finish_campaign_if_granted();
+        }
+        let Some(campaign) = &self.total.campaign else {
+            return Vec::new();
+        };
+        let (epoch, after) = (campaign.epoch, self.total.delivered);
+        let waiting: Vec<String> = self
+            .node_ids
+            .iter()
+            .filter(|node| !campaign.granted.contains(*node))
+            .cloned()
+            .collect();
+        waiting
+            .into_iter()
+            .map(|dest| BroadcastCommand::SeqPromise {
+                dest,
+                msg_id: self.next_id(),
+                epoch,
+                after,
+            })
+            .collect()
+    }
+
+    /// Smallest epoch above `promised` that belongs to us. Epochs are
+    /// `round * cluster size + our index`, so no two nodes share one.
+    fn next_epoch(&self) -> u64 {
+        let me = self._node_id.as_ref().expect("Node Id not set");
+        let size = self.node_ids.len().max(1) as u64;
+        let index = self.node_ids.iter().position(|node| node == me).unwrap_or(0) as u64;
+        (self.total.promised / size + 1) * size + index
+    }
+
+    /// Answer a candidate's promise request: grant it if its epoch is at
+    /// least the highest we know, and report what we hold after `after`.
+    pub fn promise_epoch(&mut self, epoch: u64, after: u64) -> (bool, u64, Vec<(u64, u64, u64)>) {
+        if epoch < self.total.promised {
+            return (false, self.total.promised, Vec::new());
+        }
+        self.total.promised = epoch;
+        (true, epoch, self.total.entries_after(after))
+    }
+
+    /// Record a promise reply from `src` for our campaign at `epoch`.
+    pub fn promise_received(
+        &mut self,
+        src: String,
+        epoch: u64,
+        granted: bool,
+        promised: u64,
+        entries: Vec<(u64, u64, u64)>,
+    ) {
+        if !granted {
+            // Someone is ahead of us; the next tick campaigns above it.
+            self.total.promised = self.total.promised.max(promised);
+            return;
+        }
+        let Some(campaign) = self.total.campaign.as_mut().filter(|c| c.epoch == epoch) else {
+            return;
+        };
+        campaign.granted.insert(src);
+        for (seq, entry_epoch, value) in entries {
+            let slot = campaign.recovered.entry(seq).or_insert((entry_epoch, value));
+            if entry_epoch > slot.0 {
+                *slot = (entry_epoch, value);
+            }
+        }
+        self.finish_campaign_if_granted();
+    }
+
+    fn finish_campaign_if_granted(&mut self) {
+        let quorum = self.node_ids.len() / 2 + 1;
+        let Some(campaign) = self.total.campaign.take_if(|c| c.granted.len() >= quorum) else {
+            return;
+        };
+        let epoch = campaign.epoch;
+        if epoch < self.total.promised {
+            return;
+        }
+        let me = self._node_id.clone().expect("Node Id not set");
+        // Everything in our log or reported by the quorum is re-issued under
+        // the new epoch, so nodes that promised it will accept it.
+        for (seq, (_, value)) in campaign.recovered {
+            if let Err(e) = self.learn_sequenced(me.clone(), Sequenced { epoch, seq }, Some(value)) {
+                tracing::error!(seq, error = %e, "recovered value was not logged");
+            }
+        }
+        // A position the old sequencer numbered that no quorum member holds
+        // would stall delivery everywhere; close it with a no-op.
+        let last = self.total.log.keys().next_back().copied().unwrap_or(0);
+        for seq in self.total.delivered + 1..last {
+            if !self.total.log.contains_key(&seq) {
+                self.learn_noop(me.clone(), Sequenced { epoch, seq });
+            }
+        }
+        for (seq, entry) in self.total.log.iter_mut() {
+            entry.epoch = epoch;
+            self.total.seqs.insert(entry.key, Sequenced { epoch, seq: *seq });
+        }
+        self.total.epoch = Some(epoch);
+        tracing::info!(epoch, next = self.total.log.keys().next_back().map_or(1, |s| s + 1), "sequencer epoch granted");
+    }
+
+    /// Handle a no-op numbered by a sequencer, gossiped as an empty broadcast.
+    pub fn learn_noop(&mut self, src: String, tag: Sequenced) {
+        if let Err(e) = self.learn_sequenced(src, tag, None) {
+            tracing::error!(?tag, error = %e, "no-op was not learned");
+        }
+    }
+
+    fn learn_sequenced(&mut self, src: String, tag: Sequenced, value: Option<u64>) -> anyhow::Result<()> {
+        if tag.epoch < self.total.promised {
+            tracing::debug!(?tag, promised = self.total.promised, "dropping value from a stale sequencer");
+            return Ok(());
+        }
+        self.total.promised = tag.epoch;
+        let displaces = match self.total.log.get_mut(&tag.seq) {
+            Some(existing) => {
+                if existing.value == value {
+                    existing.epoch = existing.epoch.max(tag.epoch);
+                    return Ok(());
+                }
+                if tag.epoch <= existing.epoch || tag.seq <= self.total.delivered {
+                    self.total.conflicts += 1;
+                    tracing::error!(
+                        seq = tag.seq,
+                        kept = ?existing.value,
+                        rejected = ?value,
+                        "two values sequenced at the same position"
+                    );
+                    return Ok(());
+                }
+                true
+            }
+            None => false,
+        };
+        let key = match value {
+            Some(value) => self.insert_value(src, value)?,
+            None => {
+                let key = self.next_id();
+                let peers: Vec<String> = self.topology.iter().filter(|node| **node != src).cloned().collect();
+                for peer in peers {
+                    self.add_to_pending(peer, key);
+                }
+                key
+            }
+        };
+        if displaces {
+            // Not delivered yet, and the newer epoch decides.
+            let displaced = self.total.log.remove(&tag.seq).expect("entry exists");
+            self.total.seqs.remove(&displaced.key);
+        }
+        self.total.log.insert(tag.seq, LogEntry { epoch: tag.epoch, value, key });
+        self.total.seqs.insert(key, tag);
+        self.total.advance();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn total_store(id: &str) -> Storage {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new(tx);
+        store.config.total_order_broadcast = true;
+        store.set_id(id).await;
+        let node_ids: Vec<String> = vec!["n1".into(), "n2".into(), "n3".into()];
+        store.init_members(&node_ids);
+        store.update_typology(node_ids.iter().filter(|n| *n != id).cloned().collect());
+        store.node_ids = node_ids;
+        store
+    }
+
+    /// Run `candidate`'s campaign against `voters` until it holds an epoch.
+    fn elect(candidate: &mut Storage, voters: &mut [&mut Storage]) -> u64 {
+        let requests = candidate.total_order_submissions(0);
+        for request in requests {
+            let BroadcastCommand::SeqPromise { dest, epoch, after, .. } = request else {
+                panic!("expected promise requests");
+            };
+            let Some(voter) = voters.iter_mut().find(|v| v._node_id.as_deref() == Some(&dest)) else {
+                continue;
+            };
+            let (granted, promised, entries) = voter.promise_epoch(epoch, after);
+            candidate.promise_received(dest, epoch, granted, promised, entries);
+        }
+        candidate.total.epoch.expect("candidate was not granted an epoch")
+    }
+
+    fn tag(epoch: u64, seq: u64) -> Option<Sequenced> {
+        Some(Sequenced { epoch, seq })
+    }
+
+    #[tokio::test]
+    async fn lowest_live_node_sequences() {
+        let mut store = total_store("n2").await;
+        assert_eq!(store.sequencer().map(String::as_str), Some("n1"));
+
+        store.membership.members.get_mut("n1").unwrap().state = MemberState::Dead;
+        assert!(store.is_sequencer());
+    }
+
+    #[tokio::test]
+    async fn sequenced_values_are_delivered_in_order() {
+        let mut store = total_store("n2").await;
+
+        store.broadcast_total("n1".into(), 1, 30, tag(3, 3)).unwrap();
+        store.broadcast_total("n1".into(), 2, 10, tag(3, 1)).unwrap();
+        assert_eq!(store.total.values(), vec![10]);
+
+        store.broadcast_total("n3".into(), 3, 20, tag(3, 2)).unwrap();
+        store.broadcast_total("n3".into(), 4, 10, tag(3, 1)).unwrap();
+        assert_eq!(store.total.values(), vec![10, 20, 30]);
+    }
+
+    #[tokio::test]
+    async fn followers_submit_until_acked_and_sequencer_dedupes() {
+        let mut follower = total_store("n2").await;
+        let mut sequencer = total_store("n1").await;
+        elect(&mut sequencer, &mut [&mut follower]);
+
+        follower.broadcast_total("c1".into(), 7, 42, None).unwrap();
+        follower.broadcast_total("c1".into(), 7, 42, None).unwrap();
+        assert_eq!(follower.total.outbox.len(), 1);
+        let submissions = follower.total_order_submissions(0);
+        let [BroadcastCommand::Broadcast { dest, msg_id, .. }] = submissions.as_slice() else {
+            panic!("expected one submission");
+        };
+        assert_eq!(dest, "n1");
+
+        sequencer.broadcast_total("n2".into(), *msg_id, 42, None).unwrap();
+        sequencer.broadcast_total("n2".into(), *msg_id, 42, None).unwrap();
+        assert_eq!(sequencer.total.values(), vec![42]);
+
+        follower.remove_from_peer_pending("n1".into(), *msg_id);
+        assert!(follower.total.outbox.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sequencer_waits_for_a_majority_before_numbering() {
+        let mut sequencer = total_store("n1").await;
+
+        sequencer.broadcast_total("c1".into(), 1, 5, None).unwrap();
+        let requests = sequencer.total_order_submissions(0);
+        assert_eq!(requests.len(), 2);
+        assert!(sequencer.total.values().is_empty());
+
+        let mut n2 = total_store("n2").await;
+        elect(&mut sequencer, &mut [&mut n2]);
+        sequencer.total_order_submissions(0);
+        assert_eq!(sequencer.total.values(), vec![5]);
+    }
+
+    #[tokio::test]
+    async fn new_sequencer_recovers_log_and_fences_the_old_one() {
+        let mut n1 = total_store("n1").await;
+        let mut n2 = total_store("n2").await;
+        let mut n3 = total_store("n3").await;
+        let old = elect(&mut n1, &mut [&mut n2]);
+        n1.broadcast_total("c1".into(), 1, 10, None).unwrap();
+        n2.broadcast_total("n1".into(), 2, 10, tag(old, 1)).unwrap();
+
+        // n1 is cut off; n2 takes over with n3 and must continue after 1.
+        n2.membership.members.get_mut("n1").unwrap().state = MemberState::Dead;
+        let new = elect(&mut n2, &mut [&mut n3]);
+        assert!(new > old);
+        n2.broadcast_total("c2".into(), 1, 20, None).unwrap();
+        assert_eq!(n2.total.values(), vec![10, 20]);
+
+        // The old sequencer keeps numbering; nodes that promised drop it.
+        n1.broadcast_total("c1".into(), 2, 30, None).unwrap();
+        n3.broadcast_total("n1".into(), 3, 30, tag(old, 2)).unwrap();
+        assert!(!n3.total.log.contains_key(&2));
+
+        // Where the old value was already delivered, the clash is reported.
+        n1.broadcast_total("n2".into(), 4, 20, tag(new, 2)).unwrap();
+        assert_eq!(n1.total.values(), vec![10, 30]);
+        assert_eq!(n1.total.conflicts, 1);
+    }
+
+    #[tokio::test]
+    async fn new_sequencer_closes_unrecovered_positions_with_no_ops() {
+        let mut n1 = total_store("n1").await;
+        let mut n2 = total_store("n2").await;
+        let mut n3 = total_store("n3").await;
+        let old = elect(&mut n1, &mut [&mut n2]);
+        n1.broadcast_total("c1".into(), 1, 10, None).unwrap();
+        n1.broadcast_total("c1".into(), 2, 20, None).unwrap();
+        // Only position 2 reached n2 before n1 was cut off.
+        n2.broadcast_total("n1".into(), 2, 20, tag(old, 2)).unwrap();
+        assert!(n2.total.values().is_empty());
+
+        n2.membership.members.get_mut("n1").unwrap().state = MemberState::Dead;
+        let new = elect(&mut n2, &mut [&mut n3]);
+        assert_eq!(n2.total.values(), vec![20]);
+        n2.broadcast_total("c2".into(), 1, 30, None).unwrap();
+        assert_eq!(n2.total.values(), vec![20, 30]);
+
+        // The no-op spreads like a value and unblocks the other nodes too.
+        let noop = *n2.peer_pending["n3"].iter().find(|key| n2.total.is_noop(**key)).unwrap();
+        assert_eq!(n2.total.seqs[&noop], Sequenced { epoch: new, seq: 1 });
+        n3.broadcast_total("n2".into(), 3, 20, tag(new, 2)).unwrap();
+        n3.learn_noop("n2".into(), Sequenced { epoch: new, seq: 1 });
+        assert_eq!(n3.total.values(), vec![20]);
+        assert!(n3.total.entries_after(0).iter().all(|(seq, _, _)| *seq == 2));
+    }
+
+    #[test]
+    fn accepted_submissions_are_bounded() {
+        let mut total = TotalOrder::default();
+        for msg_id in 0..ACCEPTED_CAPACITY as u64 + 10 {
+            assert!(total.accept("c1".into(), msg_id));
+        }
+        assert_eq!(total.accepted.len(), ACCEPTED_CAPACITY);
+        assert!(total.accept("c1".into(), 0));
+    }
+}
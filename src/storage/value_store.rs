@@ -1,16 +1,25 @@
-use std::collections::BTreeSet;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::{Mutex, mpsc::Sender};
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use crate::{broadcast::actor::BroadcastCommand, message::BroadcastMessage, storage::node_state::NodeStatus};
+use crate::{broadcast::actor::BroadcastCommand, config::Config, message::BroadcastMessage, storage::node_state::NodeStatus};
 
-use super::Storage;
+use super::{NodeId, Storage, actor::StorageHandle, backend::WalEntry, failure_detector::Suspicion, wait_for_node_id};
 
 impl Storage {
-    pub fn update_values(&mut self, src: String, message: u64) {
-        let id = self.node_id_to_u64();
-        let key = self.snowflake.next_id(id);
+    pub fn update_values(&mut self, src: String, message: u64) -> anyhow::Result<()> {
+        self.insert_value(src, message).map(|_| ())
+    }
+
+    /// Log a value under a fresh key, then store it and queue it for every
+    /// peer but `src`. Nothing changes if the log refuses it.
+    pub(crate) fn insert_value(&mut self, src: String, message: u64) -> anyhow::Result<u64> {
+        let key = self.next_id();
+        self.try_persist(WalEntry::Value {
+            key,
+            src: src.clone(),
+            value: message,
+        })?;
         self.values.insert(key, (src.to_string(), message));
         let nodes: Vec<String> = self.topology.iter().cloned().collect();
         for node in nodes {
@@ -18,6 +27,7 @@ impl Storage {
                 self.add_to_pending(node.clone(), key);
             }
         }
+        Ok(key)
     }
 
     pub fn remove_from_peer_pending(&mut self, node: String, key: u64) {
@@ -25,17 +35,22 @@ impl Storage {
         if let Some(candidates) = self.peer_pending.get_mut(&node) {
             candidates.remove(&key);
         }
+        tracing::trace!(%node, key = %self.key_provenance(key), "value acknowledged");
+        self.retries.reset(&node, key);
+        self.total.outbox.remove(&key);
+        self.detector(&node).heartbeat(now);
         if let Some(status) = self.node_status.get_mut(&node) {
             match status {
                 NodeStatus::Online(time) => *time = now,
                 NodeStatus::Rejoining(_, _) => *status = NodeStatus::Online(now),
                 NodeStatus::Offline(_last_seen) => {
                     *status = NodeStatus::Online(now);
+                    self.retries.reset_node(&node);
                     let known = &self.values;
                     let pending = self
                         .peer_pending
                         .entry(node.clone())
-                        .or_insert(BTreeSet::new());
+                        .or_default();
                     for k in known.keys() {
                         pending.insert(*k);
                     }
@@ -44,89 +59,120 @@ impl Storage {
         }
     }
 
-    fn add_to_pending(&mut self, node: String, key: u64) {
-        let entry = self.peer_pending.entry(node).or_insert(BTreeSet::new());
+    /// Values and counter maps still awaiting an ack, addressed to healthy peers on the online
+    /// tick and to suspected or offline peers on the slower offline tick.
+    pub fn gossip_batch(&mut self, online: bool) -> Vec<BroadcastCommand> {
+        if self._node_id.is_none() {
+            return Vec::new();
+        }
+        let nodes: Vec<String> = if online {
+            self.update_node_states();
+            self.online_nodes()
+                .filter(|node| self.suspicion(node) == Suspicion::Alive)
+                .cloned()
+                .collect()
+        } else {
+            self.offline_nodes()
+                .chain(self.suspected_nodes())
+                .cloned()
+                .collect()
+        };
+        let mut commands = self.counter_resends(&nodes);
+        commands.extend(self.pending_for(nodes));
+        if online && self.config.total_order_broadcast {
+            let now = (self.clock)();
+            commands.extend(self.total_order_submissions(now));
+        }
+        commands
+    }
+
+    /// Pending values for `nodes` whose backoff has elapsed; each one
+    /// returned is rescheduled further out.
+    fn pending_for(&mut self, nodes: Vec<String>) -> Vec<BroadcastCommand> {
+        let now = (self.clock)();
+        let (base, cap, jitter) = (
+            self.config.gossip_interval_ms,
+            self.config.gossip_backoff_max_ms,
+            self.config.gossip_backoff_jitter,
+        );
+        let mut to_send = Vec::new();
+        for node in nodes {
+            if let Some(pending) = self.peer_pending.get(&node) {
+                for key in pending.iter() {
+                    if !self.retries.is_due(&node, *key, now) {
+                        continue;
+                    }
+                    if let Some(message) = self.values.get(key) {
+                        to_send.push((node.clone(), *key, Some(message.1)));
+                    } else if self.total.is_noop(*key) {
+                        to_send.push((node.clone(), *key, None));
+                    }
+                }
+            }
+        }
+        let mut commands = Vec::with_capacity(to_send.len());
+        for (dest, msg_id, message) in to_send {
+            self.retries.record_send(&dest, msg_id, now, base, cap, jitter);
+            commands.push(BroadcastCommand::Broadcast {
+                dest,
+                msg_id,
+                message: message.map_or(BroadcastMessage::Multiple(Vec::new()), BroadcastMessage::Single),
+                causal: self.causal.tags.get(&msg_id).cloned(),
+                seq: self.total.seqs.get(&msg_id).copied(),
+            });
+        }
+        commands
+    }
+
+    pub(super) fn add_to_pending(&mut self, node: String, key: u64) {
+        let entry = self.peer_pending.entry(node.clone()).or_default();
+        let was_idle = entry.is_empty();
         entry.insert(key);
+        if was_idle {
+            // Silence while we had nothing to send says nothing about the peer.
+            let now = (self.clock)();
+            self.detector(&node).resume(now);
+        }
     }
 }
 
-pub async fn spawn_gossip_sender(arc_storage: Arc<Mutex<Storage>>, tx: Sender<BroadcastCommand>) {
-    tokio::spawn(async move {
-        let mut online_interval = tokio::time::interval(Duration::from_secs(1));
-        let mut offline_interval = tokio::time::interval(Duration::from_secs(3));
+/// Periodically retransmit unacknowledged values until `shutdown` fires.
+pub async fn spawn_gossip_sender(
+    storage: StorageHandle,
+    tx: Sender<BroadcastCommand>,
+    id: NodeId,
+    config: &Config,
+    shutdown: CancellationToken,
+) {
+    let Some(id) = wait_for_node_id(&id, &shutdown).await else {
+        return;
+    };
+    let span = tracing::info_span!("gossip", node = %id);
+    async {
+        let mut online_interval = tokio::time::interval(config.gossip_interval());
+        let mut offline_interval = tokio::time::interval(config.offline_gossip_interval());
         loop {
-            tokio::select! {
-                            _ = online_interval.tick() => {
-
-                                let mut to_send = Vec::new();
-
-                                {
-                                    let mut storage = arc_storage.lock().await;
-                                    if storage._node_id.is_none() {
-                                        continue;
-                                    }
-                                    storage.update_node_states();
-
-                                    for node in storage.online_nodes() {
-                                        if let Some(pending) = storage.peer_pending.get(node) {
-                                            for key in pending.iter() {
-                                                if let Some(message) = storage.values.get(key) {
-                                                    to_send.push((node.clone(), *key, message.1));
-                                                }
-                                            }
-                                        }
-                                    }
-                                } // lock dropped here
-
-                                for (dest, msg_id, message) in to_send {
-                                    let _ = tx
-                                        .send(BroadcastCommand::Broadcast {
-                                            dest,
-                                            msg_id,
-                                            message: BroadcastMessage::Single(message),
-                                        })
-                                        .await;
-                                }
-                            }
-
-                            _ = offline_interval.tick() => {
-
-                                let mut to_send = Vec::new();
-
-                                {
-                                    let  storage = arc_storage.lock().await;
-                                    if storage._node_id.is_none() {
-                                        continue;
-                                    }
-
-                                    for node in storage.offline_nodes() {
-                                        if let Some(pending) = storage.peer_pending.get(node) {
-                                            for key in pending.iter() {
-                                                if let Some(message) = storage.values.get(key) {
-                                                    to_send.push((node.clone(), *key, message.1));
-                                                }
-                                            }
-                                        }
-                                    }
-                                } // lock dropped here
-
-                                for (dest, msg_id, message) in to_send {
-                                    let _ = tx
-                                        .send(BroadcastCommand::Broadcast {
-                                            dest,
-                                            msg_id,
-                                                                            message: BroadcastMessage::Single(message)
-            ,
-                                        })
-                                        .await;
-                                }
-                            }
-                        }
+            let batch = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = online_interval.tick() => storage.gossip(true).await,
+                _ = offline_interval.tick() => storage.gossip(false).await,
+            };
+            let Ok(to_send) = batch else {
+                return;
+            };
+
+            for command in to_send {
+                if tx.send(command).await.is_err() {
+                    tracing::warn!("broadcast actor has stopped; ending gossip");
+                    return;
+                }
+            }
         }
-    });
+    }
+    .instrument(span)
+    .await
 }
 
-
 #[cfg(test)]
 mod tests {
     use crate::storage::Storage;
@@ -140,7 +186,7 @@ mod tests {
 
         store.update_typology(vec!["node-B".into(), "node-C".into()]);
 
-        store.update_values("node-A".to_string(), 42);
+        store.update_values("node-A".to_string(), 42).unwrap();
 
         let key = *store.values.keys().next().unwrap();
         let val = store.values.get(&key).unwrap();
@@ -163,7 +209,7 @@ mod tests {
         store.set_id("node-A").await;
 
         store.update_typology(vec!["node-B".into()]);
-        store.update_values("node-A".to_string(), 123);
+        store.update_values("node-A".to_string(), 123).unwrap();
 
         let key = *store.values.keys().next().unwrap();
 
@@ -175,4 +221,34 @@ mod tests {
             Some(NodeStatus::Online(_))
         ));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn unacked_values_back_off_and_ack_clears_schedule() {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicU64, Ordering},
+        };
+
+        let now = Arc::new(AtomicU64::new(1_000));
+        let clock = Arc::clone(&now);
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut store = Storage::new_with_clock(tx, move || clock.load(Ordering::Relaxed));
+        store.set_id("node-A").await;
+        store.update_typology(vec!["node-B".into()]);
+        store.update_values("client".into(), 7).unwrap();
+        let key = *store.values.keys().next().unwrap();
+
+        assert_eq!(store.gossip_batch(true).len(), 1);
+        // Sent once: nothing more until the first backoff delay has passed.
+        assert!(store.gossip_batch(true).is_empty());
+        let first = store.retries.get("node-B", key).unwrap();
+        now.store(first.next_at, Ordering::Relaxed);
+        assert_eq!(store.gossip_batch(true).len(), 1);
+        let second = store.retries.get("node-B", key).unwrap();
+        assert_eq!(second.attempts, 2);
+        assert!(second.next_at - first.next_at >= first.next_at - 1_000);
+
+        store.remove_from_peer_pending("node-B".into(), key);
+        assert_eq!(store.retries.get("node-B", key), None);
+    }
+}
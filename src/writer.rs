@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc::Receiver,
+};
+
+use crate::hlc::{Hlc, is_node_id};
+
+/// Counters updated by `write_stdout`, readable from any task.
+#[derive(Default)]
+pub struct WriterMetrics {
+    written: AtomicU64,
+    flushes: AtomicU64,
+    queue_depth: AtomicUsize,
+    max_queue_depth: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WriterStats {
+    pub written: u64,
+    pub flushes: u64,
+    pub queue_depth: usize,
+    pub max_queue_depth: usize,
+}
+
+impl WriterMetrics {
+    pub fn stats(&self) -> WriterStats {
+        WriterStats {
+            written: self.written.load(Ordering::Relaxed),
+            flushes: self.flushes.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            max_queue_depth: self.max_queue_depth.load(Ordering::Relaxed),
+        }
+    }
+
+    fn observe_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+        self.max_queue_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+/// Write each queued line to `writer`, flushing once per burst rather than per
+/// line. Every message to another node is stamped with `hlc` here, so senders
+/// need not. Returns an error as soon as the writer fails so the caller can
+/// shut down.
+pub async fn write_stdout<W: AsyncWrite + Unpin>(
+    writer: W,
+    mut rx: Receiver<String>,
+    hlc: &Hlc,
+    metrics: &WriterMetrics,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    while let Some(msg) = rx.recv().await {
+        metrics.observe_depth(rx.len() + 1);
+        write_line(&mut writer, &stamped(msg, hlc)).await?;
+        let mut written = 1;
+        while let Ok(msg) = rx.try_recv() {
+            write_line(&mut writer, &stamped(msg, hlc)).await?;
+            written += 1;
+        }
+        writer.flush().await.context("Failed to flush stdout")?;
+        metrics.written.fetch_add(written, Ordering::Relaxed);
+        metrics.flushes.fetch_add(1, Ordering::Relaxed);
+        metrics.observe_depth(rx.len());
+    }
+    writer.flush().await.context("Failed to flush stdout")?;
+    Ok(())
+}
+
+/// `line` with a fresh timestamp in `body.hlc` if it goes to another node.
+fn stamped(line: String, hlc: &Hlc) -> String {
+    match serde_json::from_str::<Value>(&line) {
+        Ok(mut message) if message["dest"].as_str().is_some_and(is_node_id) => {
+            hlc.stamp(&mut message);
+            message.to_string()
+        }
+        _ => line,
+    }
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut BufWriter<W>, msg: &str) -> anyhow::Result<()> {
+    tracing::trace!(line = %msg, "sent");
+    writer
+        .write_all(msg.as_bytes())
+        .await
+        .context("Failed to output to stdout")?;
+    writer
+        .write_all(b"\n")
+        .await
+        .context("Failed to output to stdout")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queued_lines_are_coalesced_into_one_flush() {
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        for i in 0..3 {
+            tx.send(format!("line {}", i)).await.unwrap();
+        }
+        drop(tx);
+
+        let metrics = WriterMetrics::default();
+        let mut out = Vec::new();
+        write_stdout(&mut out, rx, &Hlc::system(), &metrics).await.unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "line 0\nline 1\nline 2\n");
+        let stats = metrics.stats();
+        assert_eq!(stats.written, 3);
+        assert_eq!(stats.flushes, 1);
+        assert_eq!(stats.max_queue_depth, 3);
+        assert_eq!(stats.queue_depth, 0);
+    }
+
+    #[tokio::test]
+    async fn messages_to_nodes_are_stamped_and_others_are_not() {
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        tx.send(r#"{"src":"n1","dest":"n2","body":{"type":"broadcast_ok"}}"#.into()).await.unwrap();
+        tx.send(r#"{"src":"n1","dest":"c1","body":{"type":"read_ok"}}"#.into()).await.unwrap();
+        drop(tx);
+
+        let mut out = Vec::new();
+        write_stdout(&mut out, rx, &Hlc::system(), &WriterMetrics::default()).await.unwrap();
+
+        let sent: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(sent[0]["body"]["hlc"].is_u64());
+        assert!(sent[1]["body"].get("hlc").is_none());
+    }
+}
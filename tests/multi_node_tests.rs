@@ -214,6 +214,8 @@ async fn test_handle_broadcast() {
         1,
         &mut storage,
         BroadcastMessage::Single(123),
+        None,
+        None,
         tx.clone(),
     )
     .await
@@ -238,6 +240,8 @@ async fn test_send_broadcast() {
         "node2".to_string(),
         1,
         BroadcastMessage::Single(123),
+        None,
+        None,
         tx.clone(),
     )
     .await
@@ -263,7 +267,7 @@ async fn test_handle_broadcast_ok() {
     storage.update_typology(vec!["node2".to_string()]);
 
     // Simulate a pending message
-    storage.update_values("node1".to_string(), 456);
+    storage.update_values("node1".to_string(), 456).unwrap();
     let key = *storage.values.keys().next().unwrap();
 
     // Ensure it's pending for node2
@@ -277,3 +281,65 @@ async fn test_handle_broadcast_ok() {
     // Assert pending message is removed
     assert!(!storage.peer_pending.get("node2").unwrap().contains(&key));
 }
+
+#[tokio::test]
+async fn test_lin_kv_read_write_cas() {
+    let mut network = TestNetwork::new();
+    network
+        .add_node("node1".to_string(), "lin-kv".to_string())
+        .await;
+
+    network.send_message(make_kv_read_msg(1, "0"));
+    while network.tick().await {}
+    let reply = network.get_last_reply().expect("Should have received error");
+    assert_eq!(reply.body["type"], "error");
+    assert_eq!(reply.body["code"], 20);
+
+    network.send_message(make_write_msg(2, "0", 3));
+    while network.tick().await {}
+    let reply = network.get_last_reply().expect("Should have received write_ok");
+    assert_eq!(reply.body["type"], "write_ok");
+
+    let cas_line = r#"{"src":"client","dest":"node1","body":{"type":"cas","msg_id":3,"key":0,"from":3,"to":4}}"#;
+    network.send_message(serde_json::from_str(cas_line).unwrap());
+    while network.tick().await {}
+    let reply = network.get_last_reply().expect("Should have received cas_ok");
+    assert_eq!(reply.body["type"], "cas_ok");
+    assert_eq!(reply.body["in_reply_to"], 3);
+
+    network.send_message(make_kv_read_msg(4, "0"));
+    while network.tick().await {}
+    let reply = network.get_last_reply().expect("Should have received read_ok");
+    assert_eq!(reply.body["type"], "read_ok");
+    assert_eq!(reply.body["value"], 4);
+}
+
+#[tokio::test]
+async fn test_dump_returns_loadable_snapshot() {
+    let mut network = TestNetwork::new();
+    network
+        .add_node("node1".to_string(), "broadcast".to_string())
+        .await;
+
+    network.send_message(make_broadcast_msg(1, 100));
+    while network.tick().await {}
+    let _ = network.get_last_reply();
+
+    network.send_message(Message {
+        src: "client".to_string(),
+        dest: "node1".to_string(),
+        body: Body::Dump { msg_id: 2 },
+    });
+    while network.tick().await {}
+    let reply = network.get_last_reply().expect("Should have received dump_ok");
+    assert_eq!(reply.body["type"], "dump_ok");
+
+    let (tx, _rx) = mpsc::channel(10);
+    let mut restored = Storage::new(tx);
+    restored
+        .load_snapshot_json(&reply.body["snapshot"].to_string())
+        .await
+        .unwrap();
+    assert_eq!(restored.values(), vec![100]);
+    assert_eq!(restored.workload.as_deref(), Some("broadcast"));
+}
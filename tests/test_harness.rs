@@ -51,6 +51,12 @@ pub struct TestNetwork {
     received_replies: VecDeque<ReplyMessage>,
 }
 
+impl Default for TestNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TestNetwork {
     pub fn new() -> Self {
         Self {
@@ -58,6 +58,8 @@ pub fn make_broadcast_msg(msg_id: u64, message: u64) -> Message {
         body: Body::Broadcast {
             msg_id,
             message: maelstrom_rust_node::message::BroadcastMessage::Single(message),
+            causal: None,
+            seq: None,
         },
     }
 }
@@ -86,4 +88,28 @@ pub fn make_topology_msg(msg_id: u64) -> Message {
             topology,
         },
     }
-}
\ No newline at end of file
+}
+#[allow(dead_code)]
+pub fn make_kv_read_msg(msg_id: u64, key: &str) -> Message {
+    Message {
+        src: "client".to_string(),
+        dest: "node1".to_string(),
+        body: Body::Read {
+            msg_id,
+            key: Some(key.to_string()),
+        },
+    }
+}
+
+#[allow(dead_code)]
+pub fn make_write_msg(msg_id: u64, key: &str, value: u64) -> Message {
+    Message {
+        src: "client".to_string(),
+        dest: "node1".to_string(),
+        body: Body::Write {
+            msg_id,
+            key: key.to_string(),
+            value,
+        },
+    }
+}